@@ -0,0 +1,9 @@
+-- Which run is working on a message, so the push webhook and the cron never
+-- draft the same message twice. `claimed_at` is in Unix seconds; claims older
+-- than the worker's claim TTL may be taken over or pruned.
+CREATE TABLE IF NOT EXISTS message_claims (
+    account TEXT NOT NULL,
+    message_id TEXT NOT NULL,
+    claimed_at INTEGER NOT NULL,
+    PRIMARY KEY (account, message_id)
+);
@@ -0,0 +1,80 @@
+use crate::models::AccountSettings;
+use worker::*;
+
+/// KV key holding the JSON array of mailbox addresses this deployment serves.
+const ACCOUNTS_KEY: &str = "accounts";
+/// Refresh token key used before multi-account support, still honoured so
+/// existing single-mailbox deployments keep working.
+const LEGACY_REFRESH_TOKEN_KEY: &str = "refresh_token";
+
+/// Builds the KV key for one piece of per-account state, e.g.
+/// `account:jane@example.com:refresh_token`.
+pub fn account_key(email: &str, name: &str) -> String {
+    format!("account:{}:{}", email.to_lowercase(), name)
+}
+
+/// Returns every registered mailbox. Falls back to the `USER_EMAIL` secret,
+/// if set, when no account has been registered yet.
+pub async fn list_accounts(env: &Env) -> Result<Vec<String>> {
+    let kv = env.kv("GMAIL_AUTH")?;
+    match kv.get(ACCOUNTS_KEY).json::<Vec<String>>().await? {
+        Some(accounts) if !accounts.is_empty() => Ok(accounts),
+        _ => Ok(env
+            .secret("USER_EMAIL")
+            .map(|user_email| user_email.to_string())
+            .into_iter()
+            .collect()),
+    }
+}
+
+/// Adds `email` to the account list if it is not there yet. The first
+/// registration keeps the `USER_EMAIL` fallback account in the list.
+pub async fn register_account(env: &Env, email: &str) -> Result<()> {
+    let kv = env.kv("GMAIL_AUTH")?;
+    let mut accounts = list_accounts(env).await?;
+
+    if !accounts.iter().any(|a| a.eq_ignore_ascii_case(email)) {
+        accounts.push(email.to_string());
+        kv.put(ACCOUNTS_KEY, accounts)?.execute().await?;
+    }
+    Ok(())
+}
+
+/// The mailbox's stored settings. Without any, the `USER_EMAIL` account
+/// keeps its original settings and every other account gets neutral ones.
+pub async fn load_settings(env: &Env, kv: &kv::KvStore, email: &str) -> Result<AccountSettings> {
+    if let Some(settings) = kv
+        .get(&account_key(email, "settings"))
+        .json::<AccountSettings>()
+        .await?
+    {
+        return Ok(settings);
+    }
+
+    let is_legacy_account = env
+        .secret("USER_EMAIL")
+        .is_ok_and(|user_email| user_email.to_string().eq_ignore_ascii_case(email));
+    Ok(if is_legacy_account {
+        AccountSettings::legacy()
+    } else {
+        AccountSettings::default()
+    })
+}
+
+pub async fn load_refresh_token(
+    env: &Env,
+    kv: &kv::KvStore,
+    email: &str,
+) -> Result<Option<String>> {
+    if let Some(token) = kv.get(&account_key(email, "refresh_token")).text().await? {
+        return Ok(Some(token));
+    }
+
+    let is_legacy_account = env
+        .secret("USER_EMAIL")
+        .is_ok_and(|user_email| user_email.to_string().eq_ignore_ascii_case(email));
+    if is_legacy_account {
+        return Ok(kv.get(LEGACY_REFRESH_TOKEN_KEY).text().await?);
+    }
+    Ok(None)
+}
@@ -0,0 +1,94 @@
+use super::DriveProvider;
+use crate::error::{Api, ApiError};
+use crate::models::{AttachmentData, DriveFile};
+use async_trait::async_trait;
+use std::cell::RefCell;
+
+struct FakeFile {
+    file: DriveFile,
+    data: AttachmentData,
+}
+
+/// In-memory Drive for exercising the pipeline without the Drive API. Only
+/// `name contains '<word>'` clauses joined by `or` are understood by
+/// `search_files`; exports return the file's data unchanged.
+#[derive(Default)]
+pub struct FakeDriveProvider {
+    files: RefCell<Vec<FakeFile>>,
+}
+
+impl FakeDriveProvider {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_file(&self, id: &str, name: &str, mime_type: &str, data: &[u8]) {
+        self.files.borrow_mut().push(FakeFile {
+            file: DriveFile {
+                id: id.to_string(),
+                name: name.to_string(),
+                web_view_link: format!("https://drive.google.com/file/d/{}/view", id),
+                mime_type: mime_type.to_string(),
+            },
+            data: data.to_vec(),
+        });
+    }
+
+    fn data(&self, file_id: &str) -> Result<AttachmentData, ApiError> {
+        self.files
+            .borrow()
+            .iter()
+            .find(|f| f.file.id == file_id)
+            .map(|f| f.data.clone())
+            .ok_or_else(|| Self::not_found(file_id))
+    }
+
+    fn not_found(file_id: &str) -> ApiError {
+        ApiError::http(Api::Drive, 404, &format!("File not found: {}.", file_id))
+    }
+}
+
+#[async_trait(?Send)]
+impl DriveProvider for FakeDriveProvider {
+    async fn get_file(&self, file_id: &str) -> Result<DriveFile, ApiError> {
+        self.files
+            .borrow()
+            .iter()
+            .find(|f| f.file.id == file_id)
+            .map(|f| f.file.clone())
+            .ok_or_else(|| Self::not_found(file_id))
+    }
+
+    async fn search_files(&self, query: &str) -> Result<Vec<DriveFile>, ApiError> {
+        let words: Vec<String> = query
+            .split(" or ")
+            .filter_map(|clause| {
+                clause
+                    .trim()
+                    .strip_prefix("name contains '")?
+                    .strip_suffix('\'')
+                    .map(|word| word.replace("\\'", "'").replace("\\\\", "\\"))
+            })
+            .collect();
+
+        Ok(self
+            .files
+            .borrow()
+            .iter()
+            .filter(|f| words.iter().any(|word| f.file.name.contains(word.as_str())))
+            .map(|f| f.file.clone())
+            .collect())
+    }
+
+    async fn download_file(&self, file_id: &str) -> Result<AttachmentData, ApiError> {
+        self.data(file_id)
+    }
+
+    async fn export_file(
+        &self,
+        file_id: &str,
+        _mime_type: &str,
+    ) -> Result<AttachmentData, ApiError> {
+        self.data(file_id)
+    }
+}
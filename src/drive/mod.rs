@@ -1 +1,24 @@
 pub mod client;
+#[cfg(test)]
+pub mod fake;
+pub mod provider;
+
+use crate::error::ApiError;
+use crate::models::{AttachmentData, DriveFile};
+use async_trait::async_trait;
+
+/// The Drive operations the pipeline needs. `provider::GoogleDriveProvider`
+/// talks to the Drive API; `fake::FakeDriveProvider` keeps files in memory.
+#[async_trait(?Send)]
+pub trait DriveProvider {
+    async fn get_file(&self, file_id: &str) -> Result<DriveFile, ApiError>;
+
+    /// Files matching a Drive search `query`, e.g. `name contains 'invoice'`.
+    async fn search_files(&self, query: &str) -> Result<Vec<DriveFile>, ApiError>;
+
+    async fn download_file(&self, file_id: &str) -> Result<AttachmentData, ApiError>;
+
+    /// Exports a Google Docs, Sheets or Slides file as `mime_type`.
+    async fn export_file(&self, file_id: &str, mime_type: &str)
+        -> Result<AttachmentData, ApiError>;
+}
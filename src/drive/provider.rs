@@ -0,0 +1,40 @@
+use super::{client, DriveProvider};
+use crate::error::ApiError;
+use crate::models::{AttachmentData, DriveFile};
+use crate::oauth::token::TokenManager;
+use async_trait::async_trait;
+use std::rc::Rc;
+
+/// `DriveProvider` backed by the Drive API, with the mailbox's credentials.
+pub struct GoogleDriveProvider {
+    tokens: Rc<TokenManager>,
+}
+
+impl GoogleDriveProvider {
+    pub fn new(tokens: Rc<TokenManager>) -> Self {
+        Self { tokens }
+    }
+}
+
+#[async_trait(?Send)]
+impl DriveProvider for GoogleDriveProvider {
+    async fn get_file(&self, file_id: &str) -> Result<DriveFile, ApiError> {
+        client::get_file(&self.tokens, file_id).await
+    }
+
+    async fn search_files(&self, query: &str) -> Result<Vec<DriveFile>, ApiError> {
+        client::search_files(&self.tokens, query).await
+    }
+
+    async fn download_file(&self, file_id: &str) -> Result<AttachmentData, ApiError> {
+        client::download_file(&self.tokens, file_id).await
+    }
+
+    async fn export_file(
+        &self,
+        file_id: &str,
+        mime_type: &str,
+    ) -> Result<AttachmentData, ApiError> {
+        client::export_file(&self.tokens, file_id, mime_type).await
+    }
+}
@@ -0,0 +1,282 @@
+use serde::Deserialize;
+use std::fmt;
+
+/// The API a request was made against.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Api {
+    Gmail,
+    Drive,
+    OAuth,
+    Gemini,
+    /// A self-hosted or third-party OpenAI-compatible LLM server.
+    OpenAiCompatible,
+}
+
+impl fmt::Display for Api {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Api::Gmail => "Gmail",
+            Api::Drive => "Drive",
+            Api::OAuth => "OAuth",
+            Api::Gemini => "Gemini",
+            Api::OpenAiCompatible => "OpenAI-compatible",
+        };
+        f.write_str(name)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// The API answered with a non-success HTTP status.
+    Http,
+    /// The request never got a response.
+    Network,
+    /// The API answered successfully but the body was not what we expected.
+    Parse,
+    /// Reading or writing cached state (KV) failed.
+    Storage,
+}
+
+/// The error object Google APIs return, normalized across the
+/// `{"error": {...}}` shape of the REST APIs and the
+/// `{"error": "...", "error_description": "..."}` shape of the token endpoint.
+#[derive(Debug, Clone)]
+pub struct GoogleError {
+    pub code: Option<u16>,
+    /// e.g. `UNAUTHENTICATED`, `RESOURCE_EXHAUSTED`, or `invalid_grant` for OAuth.
+    pub status: Option<String>,
+    pub message: String,
+    /// `errors[].reason` values such as `rateLimitExceeded`.
+    pub reasons: Vec<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum GoogleErrorBody {
+    Rest {
+        error: RestError,
+    },
+    OAuth {
+        error: String,
+        error_description: Option<String>,
+    },
+}
+
+#[derive(Deserialize)]
+struct RestError {
+    code: Option<u16>,
+    message: Option<String>,
+    status: Option<String>,
+    #[serde(default)]
+    errors: Vec<RestErrorDetail>,
+}
+
+#[derive(Deserialize)]
+struct RestErrorDetail {
+    reason: Option<String>,
+}
+
+impl GoogleError {
+    pub fn parse(body: &str) -> Option<Self> {
+        match serde_json::from_str::<GoogleErrorBody>(body).ok()? {
+            GoogleErrorBody::Rest { error } => Some(Self {
+                code: error.code,
+                status: error.status,
+                message: error.message.unwrap_or_default(),
+                reasons: error.errors.into_iter().filter_map(|e| e.reason).collect(),
+            }),
+            GoogleErrorBody::OAuth {
+                error,
+                error_description,
+            } => Some(Self {
+                code: None,
+                message: error_description.unwrap_or_else(|| error.clone()),
+                status: Some(error),
+                reasons: Vec::new(),
+            }),
+        }
+    }
+}
+
+/// Error returned by the Gmail, Drive, OAuth and Gemini clients.
+#[derive(Debug, Clone)]
+pub struct ApiError {
+    pub api: Api,
+    pub kind: ErrorKind,
+    /// HTTP status of the failed response, if there was one.
+    pub status: Option<u16>,
+    /// Whether retrying the same request later may succeed.
+    pub retryable: bool,
+    pub google_error: Option<GoogleError>,
+    pub message: String,
+}
+
+impl ApiError {
+    pub fn network(api: Api, error: reqwest::Error) -> Self {
+        Self {
+            api,
+            kind: ErrorKind::Network,
+            status: None,
+            retryable: true,
+            google_error: None,
+            message: format!("Reqwest error: {}", error),
+        }
+    }
+
+    pub fn parse(api: Api, message: impl Into<String>) -> Self {
+        Self {
+            api,
+            kind: ErrorKind::Parse,
+            status: None,
+            retryable: false,
+            google_error: None,
+            message: message.into(),
+        }
+    }
+
+    pub fn storage(api: Api, error: impl fmt::Display) -> Self {
+        Self {
+            api,
+            kind: ErrorKind::Storage,
+            status: None,
+            retryable: true,
+            google_error: None,
+            message: format!("Storage error: {}", error),
+        }
+    }
+
+    /// Builds the error for a non-success response from its status and body.
+    pub fn http(api: Api, status: u16, body: &str) -> Self {
+        let google_error = GoogleError::parse(body);
+        let message = google_error
+            .as_ref()
+            .map(|e| e.message.clone())
+            .filter(|m| !m.is_empty())
+            .unwrap_or_else(|| body.to_string());
+
+        let mut error = Self {
+            api,
+            kind: ErrorKind::Http,
+            status: Some(status),
+            retryable: false,
+            google_error,
+            message,
+        };
+        error.retryable = status >= 500 || error.is_quota_exceeded();
+        error
+    }
+
+    /// Consumes a response, returning it unchanged on success and as an
+    /// `ApiError` otherwise.
+    pub async fn check(api: Api, res: reqwest::Response) -> Result<reqwest::Response, Self> {
+        if res.status().is_success() {
+            return Ok(res);
+        }
+        let status = res.status().as_u16();
+        let body = res
+            .text()
+            .await
+            .unwrap_or_else(|_| "Unknown error".to_string());
+        Err(Self::http(api, status, &body))
+    }
+
+    /// The credentials were rejected: an expired or revoked access token, a
+    /// revoked refresh token (`invalid_grant`) or an invalid API key.
+    pub fn is_auth_expired(&self) -> bool {
+        self.status == Some(401)
+            || self
+                .google_error
+                .as_ref()
+                .is_some_and(|e| e.status.as_deref() == Some("invalid_grant"))
+    }
+
+    /// Rate limit or quota exhaustion. Google reports these as 429, or as 403
+    /// with a rate-limit reason.
+    pub fn is_quota_exceeded(&self) -> bool {
+        const QUOTA_REASONS: [&str; 4] = [
+            "rateLimitExceeded",
+            "userRateLimitExceeded",
+            "quotaExceeded",
+            "dailyLimitExceeded",
+        ];
+
+        self.status == Some(429)
+            || self.google_error.as_ref().is_some_and(|e| {
+                e.status.as_deref() == Some("RESOURCE_EXHAUSTED")
+                    || e.reasons
+                        .iter()
+                        .any(|r| QUOTA_REASONS.contains(&r.as_str()))
+            })
+    }
+
+    pub fn is_not_found(&self) -> bool {
+        self.status == Some(404)
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.status {
+            Some(status) => write!(f, "{} API error ({}): {}", self.api, status, self.message),
+            None => write!(f, "{} API error: {}", self.api, self.message),
+        }
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+impl From<ApiError> for worker::Error {
+    fn from(error: ApiError) -> Self {
+        worker::Error::RustError(error.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rate_limits_are_quota_errors_and_retryable() {
+        let too_many = ApiError::http(Api::Gemini, 429, "Too Many Requests");
+        assert!(too_many.is_quota_exceeded());
+        assert!(too_many.retryable);
+
+        let forbidden = ApiError::http(
+            Api::Gmail,
+            403,
+            r#"{"error": {"code": 403, "message": "Rate limit exceeded",
+                "errors": [{"reason": "userRateLimitExceeded"}]}}"#,
+        );
+        assert!(forbidden.is_quota_exceeded());
+        assert!(forbidden.retryable);
+        assert_eq!(forbidden.message, "Rate limit exceeded");
+    }
+
+    #[test]
+    fn other_client_errors_are_permanent() {
+        let forbidden = ApiError::http(
+            Api::Drive,
+            403,
+            r#"{"error": {"code": 403, "message": "The user does not have sufficient permissions",
+                "errors": [{"reason": "insufficientFilePermissions"}]}}"#,
+        );
+        assert!(!forbidden.is_quota_exceeded());
+        assert!(!forbidden.retryable);
+
+        assert!(ApiError::http(Api::Gmail, 503, "Service Unavailable").retryable);
+    }
+
+    #[test]
+    fn revoked_refresh_token_is_an_auth_error() {
+        let error = ApiError::http(
+            Api::OAuth,
+            400,
+            r#"{"error": "invalid_grant", "error_description": "Token has been expired or revoked."}"#,
+        );
+        assert!(error.is_auth_expired());
+        assert!(!error.retryable);
+        assert_eq!(error.message, "Token has been expired or revoked.");
+
+        assert!(ApiError::http(Api::Gmail, 401, "").is_auth_expired());
+    }
+}
@@ -1,12 +1,23 @@
+use crate::error::{Api, ApiError};
 use crate::models::*;
-use reqwest::StatusCode;
-use worker::*;
 
-pub async fn call_gemini(api_key: &str, prompt: &str) -> Result<String> {
+type Result<T> = std::result::Result<T, ApiError>;
+
+pub const DEFAULT_MODEL: &str = "gemini-2.5-flash";
+pub const DEFAULT_EMBEDDING_MODEL: &str = "text-embedding-004";
+
+/// Generates text for `prompt`. With a `response_schema` (standard JSON
+/// Schema), Gemini is asked for `application/json` output matching it.
+pub async fn call_gemini(
+    api_key: &str,
+    model: &str,
+    prompt: &str,
+    response_schema: Option<&serde_json::Value>,
+) -> Result<String> {
     let client = reqwest::Client::new();
     let url = format!(
-        "https://generativelanguage.googleapis.com/v1beta/models/gemini-2.5-flash:generateContent?key={}",
-        api_key
+        "https://generativelanguage.googleapis.com/v1beta/models/{}:generateContent?key={}",
+        model, api_key
     );
 
     let body = GeminiRequest {
@@ -15,6 +26,10 @@ pub async fn call_gemini(api_key: &str, prompt: &str) -> Result<String> {
                 text: prompt.to_string(),
             }],
         }],
+        generation_config: response_schema.map(|schema| GenerationConfig {
+            response_mime_type: "application/json".to_string(),
+            response_schema: to_gemini_schema(schema),
+        }),
     };
 
     let res = client
@@ -22,46 +37,31 @@ pub async fn call_gemini(api_key: &str, prompt: &str) -> Result<String> {
         .json(&body)
         .send()
         .await
-        .map_err(|e| Error::from(format!("Reqwest error: {}", e)))?;
-
-    if !res.status().is_success() {
-        let status = res.status();
-
-        let error_text = res
-            .text()
-            .await
-            .unwrap_or_else(|_| "Unknown error".to_string());
-
-        let error_messages = match status {
-            StatusCode::UNAUTHORIZED => "Invalid API Key".to_string(),
-            StatusCode::FORBIDDEN => {
-                "Refresh token is no longer valid or has been revoked".to_string()
-            }
-            StatusCode::TOO_MANY_REQUESTS => "Rate limit exceeded".to_string(),
-            _ => format!("Gemini API Error: {}/Status: {}", error_text, status),
-        };
-        return Err(Error::from(error_messages));
-    }
+        .map_err(|e| ApiError::network(Api::Gemini, e))?;
+    let res = ApiError::check(Api::Gemini, res).await?;
 
     let response_data = res
         .json::<GeminiResponse>()
         .await
-        .map_err(|e| Error::from(format!("JSON parsing error: {}", e)))?;
+        .map_err(|e| ApiError::parse(Api::Gemini, format!("JSON parsing error: {}", e)))?;
 
-    if let Some(candidate) = response_data.candidates.get(0) {
-        if let Some(part) = candidate.content.parts.get(0) {
+    if let Some(candidate) = response_data.candidates.first() {
+        if let Some(part) = candidate.content.parts.first() {
             return Ok(part.text.clone());
         }
     }
 
-    Err(Error::from("Could not extract text from Gemini response"))
+    Err(ApiError::parse(
+        Api::Gemini,
+        "Could not extract text from Gemini response",
+    ))
 }
 
-pub async fn get_embedding(api_key: &str, text: &str) -> Result<Vec<f32>> {
+pub async fn get_embedding(api_key: &str, model: &str, text: &str) -> Result<Vec<f32>> {
     let client = reqwest::Client::new();
     let url = format!(
-        "https://generativelanguage.googleapis.com/v1beta/models/text-embedding-004:embedContent?key={}",
-        api_key
+        "https://generativelanguage.googleapis.com/v1beta/models/{}:embedContent?key={}",
+        model, api_key
     );
 
     let body = EmbeddingRequest {
@@ -77,24 +77,36 @@ pub async fn get_embedding(api_key: &str, text: &str) -> Result<Vec<f32>> {
         .json(&body)
         .send()
         .await
-        .map_err(|e| Error::from(format!("Reqwest error during embedding: {}", e)))?;
-
-    if !res.status().is_success() {
-        let status = res.status();
-        let error_text = res
-            .text()
-            .await
-            .unwrap_or_else(|_| "Unknown error".to_string());
-        return Err(Error::from(format!(
-            "Gemini Embedding API Error: {} (Status: {})",
-            error_text, status
-        )));
-    }
+        .map_err(|e| ApiError::network(Api::Gemini, e))?;
+    let res = ApiError::check(Api::Gemini, res).await?;
 
-    let response_data = res
-        .json::<EmbeddingResponse>()
-        .await
-        .map_err(|e| Error::from(format!("JSON parsing error during embedding: {}", e)))?;
+    let response_data = res.json::<EmbeddingResponse>().await.map_err(|e| {
+        ApiError::parse(
+            Api::Gemini,
+            format!("JSON parsing error during embedding: {}", e),
+        )
+    })?;
 
     Ok(response_data.embedding.values)
 }
+
+/// Gemini's `responseSchema` is an OpenAPI subset whose `type` values are
+/// upper-case enum names (`OBJECT`, `STRING`, ...).
+fn to_gemini_schema(schema: &serde_json::Value) -> serde_json::Value {
+    match schema {
+        serde_json::Value::Object(map) => serde_json::Value::Object(
+            map.iter()
+                .map(|(key, value)| match (key.as_str(), value) {
+                    ("type", serde_json::Value::String(ty)) => {
+                        (key.clone(), serde_json::Value::String(ty.to_uppercase()))
+                    }
+                    _ => (key.clone(), to_gemini_schema(value)),
+                })
+                .collect(),
+        ),
+        serde_json::Value::Array(items) => {
+            serde_json::Value::Array(items.iter().map(to_gemini_schema).collect())
+        }
+        other => other.clone(),
+    }
+}
@@ -1,4 +1,11 @@
-pub fn get_classification_prompt(from: &str, subject: &str, body: &str) -> String {
+use crate::models::{AccountSettings, ContextualDocument};
+
+pub fn get_classification_prompt(
+    from: &str,
+    subject: &str,
+    body: &str,
+    settings: &AccountSettings,
+) -> String {
     format!(
         r#"
     # EXAMPLES
@@ -15,7 +22,7 @@ pub fn get_classification_prompt(from: &str, subject: &str, body: &str) -> Strin
     Emika
 
     ## OUTPUT 1
-    IS_FILE_REQUEST
+    {{"intent": "FILE_REQUEST", "confidence": 0.95, "language": "en", "reason": "Asks for last week's attendance report."}}
 
     ## INPUT EMAIL 2
 
@@ -33,7 +40,7 @@ pub fn get_classification_prompt(from: &str, subject: &str, body: &str) -> Strin
     単
 
     ## OUTPUT 2
-    IS_FILE_REQUEST
+    {{"intent": "FILE_REQUEST", "confidence": 0.9, "language": "ja", "reason": "Asks for this week's attendance list to be sent."}}
 
     ## INPUT EMAIL 3
 
@@ -52,44 +59,77 @@ pub fn get_classification_prompt(from: &str, subject: &str, body: &str) -> Strin
 
 
     ## OUTPUT 3
-    YES
+    {{"intent": "REPLY", "confidence": 0.9, "language": "ja", "reason": "Asks about tomorrow's schedule."}}
 
     ---
 
     # WHO YOU ARE
-    You are an AI assistant for John Tashiro. Your task is to analyze an email and determine if it requires a personal reply from John.
+    You are an AI assistant for {owner_name}. Your task is to analyze an email and determine if it requires a personal reply from {sign_off_name}.
 
     ---
 
     # INSTRUCTIONS
     The input email will come in email format.
 
-    Expected output: YES, NO, or IS_FILE_REQUEST
-    - YES // When the analyzed email requires a personal reply from John.
-    - NO // When the analyzed email doesn't require a personal reply from John. When it's a test email. When the email is just thanking John. When the email.
-    - IS_FILE_REQUEST // When the analyzed email requires a personal reply from John and requires a file attachment.
+    Expected output: a single JSON object with these fields and nothing else.
+    - intent: one of REPLY, NO_REPLY or FILE_REQUEST
+        - REPLY // When the analyzed email requires a personal reply from {sign_off_name}.
+        - NO_REPLY // When the analyzed email doesn't require a personal reply from {sign_off_name}. When it's a test email. When the email is just thanking {sign_off_name}.
+        - FILE_REQUEST // When the analyzed email requires a personal reply from {sign_off_name} and requires a file attachment.
+    - confidence: a number from 0 to 1 saying how sure you are of the intent.
+    - language: the ISO 639-1 code of the language the email is written in (e.g. "ja", "en").
+    - reason: one short sentence explaining the intent.
 
     ---
 
     # INPUT EMAIL
 
-    From: {}
-    Subject: {}
-    Body: {}
+    From: {from}
+    Subject: {subject}
+    Body: {body}
 
     "#,
-        from, subject, body
+        owner_name = settings.owner_name,
+        sign_off_name = settings.sign_off_name,
+        from = from,
+        subject = subject,
+        body = body
     )
 }
 
+/// JSON schema the classification reply must follow; mirrors
+/// `models::Classification`.
+pub fn get_classification_schema() -> serde_json::Value {
+    serde_json::json!({
+        "type": "object",
+        "properties": {
+            "intent": {
+                "type": "string",
+                "enum": ["REPLY", "NO_REPLY", "FILE_REQUEST"]
+            },
+            "confidence": { "type": "number" },
+            "language": { "type": "string" },
+            "reason": { "type": "string" }
+        },
+        "required": ["intent", "confidence", "language", "reason"]
+    })
+}
+
+/// `thread_transcript` holds the earlier messages of the conversation (see
+/// `mail::thread::transcript`) and `context_documents` the knowledge
+/// snippets retrieved for it; either section is left out when
+/// empty.
 pub fn get_drafting_prompt(
     from: &str,
     subject: &str,
     body: &str,
-    file_path: Option<String>,
+    thread_transcript: &str,
+    context_documents: &[ContextualDocument],
+    attachment_names: &[String],
+    settings: &AccountSettings,
 ) -> String {
-    let (file_attachment_example, file_attachment_instruction, file_info) = if let Some(path) =
-        file_path
+    let (file_attachment_example, file_attachment_instruction, file_info) = if !attachment_names
+        .is_empty()
     {
         let example = r#"
             ---
@@ -99,7 +139,7 @@ pub fn get_drafting_prompt(
             From: Emika <emika@example.com>
             Subject: Attendance Report
             Body: Hi John, Can you provide the Attendance Report for last week?
-            Attached File: /path/to/Attendance-Report.pdf
+            Attached Files: Attendance-Report.pdf
             Hi Emika,
 
             Thanks for reaching out.
@@ -107,15 +147,55 @@ pub fn get_drafting_prompt(
             Please find the attendance report for last week attached.
 
             Best regards,
-            John
             "#;
-        let instruction = r#"- If a file is attached, state in the email body that the file is attached (e.g., "Please find the file attached."). Do not say you will send it later."#;
-        let info = format!("- Attached File: {}\n", path);
+        let instruction = r#"- If files are attached, state in the email body that they are attached (e.g., "Please find the file attached.", or "Please find the attendance report and the coverage plan attached." for several). Do not say you will send them later."#;
+        let info = format!("- Attached Files: {}\n", attachment_names.join(", "));
         (example, instruction, info)
     } else {
         ("", "", String::new())
     };
 
+    let thread_section = if thread_transcript.is_empty() {
+        String::new()
+    } else {
+        format!(
+            "# EARLIER MESSAGES IN THIS THREAD\n    Oldest first. Keep the reply consistent with what was already said or agreed here, and do not ask again for information given here.\n\n{}\n\n    ---\n",
+            thread_transcript
+        )
+    };
+
+    let context_section = if context_documents.is_empty() {
+        String::new()
+    } else {
+        let documents = context_documents
+            .iter()
+            .enumerate()
+            .map(|(i, document)| {
+                let date = chrono::DateTime::from_timestamp(document.created_at, 0)
+                    .map(|date| date.format("%Y-%m-%d").to_string())
+                    .unwrap_or_default();
+                let origin = [document.source.as_str(), date.as_str()]
+                    .into_iter()
+                    .filter(|part| !part.is_empty())
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                format!("[{}] ({})\n{}", i + 1, origin, document.text.trim())
+            })
+            .collect::<Vec<_>>()
+            .join("\n\n");
+        format!(
+            "# RELEVANT CONTEXT\n    Notes from {}'s knowledge base that may relate to this email, most relevant first. Use them for facts, and ignore any that do not apply. Do not state anything they do not support, and do not copy them word for word.\n\n{}\n\n    ---\n",
+            settings.owner_name, documents
+        )
+    };
+
+    let context_notes = settings
+        .context_notes
+        .iter()
+        .map(|note| format!("- **CONTEXT**: {}", note))
+        .collect::<Vec<_>>()
+        .join("\n    ");
+
     format!(
         r#"
     # EXAMPLES
@@ -132,8 +212,6 @@ pub fn get_drafting_prompt(
 
     何卒よろしくお願いいたします。
 
-    John
-
     ---
 
     ## OUTPUT EMAIL 2 (INTERNAL - CONTEXTUAL)
@@ -150,8 +228,6 @@ pub fn get_drafting_prompt(
 
     何卒よろしくお願いいたします。
 
-    John
-
     ---
 
     ## OUTPUT EMAIL 3 (EXTERNAL)
@@ -168,8 +244,6 @@ pub fn get_drafting_prompt(
 
     何卒よろしくお願いいたします。
 
-    John
-
     ---
 
     ## OUTPUT EMAIL 4 (ENGLISH)
@@ -182,13 +256,12 @@ pub fn get_drafting_prompt(
     Thanks for reaching out. I'm happy to help. What's your question about the report?
 
     Best regards,
-    John
     {file_attachment_example}
 
     ---
 
     # WHO YOU ARE
-    You are an AI assistant for John Tashiro. Your task is to draft a polite and professional reply to the following email. Keep the reply concise and helpful.
+    You are an AI assistant for {owner_name}. Your task is to draft a polite and professional reply to the following email. Keep the reply concise and helpful.
 
     ---
 
@@ -198,9 +271,9 @@ pub fn get_drafting_prompt(
     ## SPECIFICS:
 
     ### GENERAL
-    - Sign off as 'John'.
+    - End the email with the closing phrase. Do not add a name or signature after it; the signature is appended automatically.
     - No need to create a draft for 'test' emails (e.g., containing 'It's a test', 'Test 1', 'Test2', 'テスト', 'テストです！').
-    - **CONTEXT**: "A6" refers to a team. In a Japanese reply, use "A6チーム".
+    {context_notes}
     {file_attachment_instruction}
 
     ### ENGLISH EMAILS
@@ -211,8 +284,8 @@ pub fn get_drafting_prompt(
     ### JAPANESE EMAILS
     - **ADDRESSING THE SENDER:**
         - Use their last name. If it's in Kanji, use Kanji. If it's in Romaji, use Romaji.
-        - Append `様` for **external** contacts (e.g., `鈴木様`, `Tanaka様`). An external contact is someone who addresses John with `様`.
-        - Append `さん` for **internal** colleagues (e.g., `坂本さん`, `Yamashita-san`). An internal colleague is someone who does NOT address John with `様`.
+        - Append `様` for **external** contacts (e.g., `鈴木様`, `Tanaka様`). An external contact is someone who addresses {sign_off_name} with `様`.
+        - Append `さん` for **internal** colleagues (e.g., `坂本さん`, `Yamashita-san`). An internal colleague is someone who does NOT address {sign_off_name} with `様`.
 
     - **OPENING GREETING:**
         - For **external** contacts, use a formal opening like `お世話になっております。`. DO NOT use `お疲れ様です。`.
@@ -221,10 +294,12 @@ pub fn get_drafting_prompt(
     - **BODY:**
         - Do not add extra blank lines between sentences in the body. The body should be a single block of text.
     - **CLOSING:**
-        - The closing should be `何卒よろしくお願いいたします。` with `John` on a new line after a blank line.
+        - The closing should be `何卒よろしくお願いいたします。`, with nothing after it.
 
     ---
 
+    {context_section}
+    {thread_section}
     # INPUT EMAIL
     - From: {from}
     - Subject: {subject}
@@ -233,6 +308,11 @@ pub fn get_drafting_prompt(
     {file_info}
 
     "#,
+        owner_name = settings.owner_name,
+        sign_off_name = settings.sign_off_name,
+        context_notes = context_notes,
+        context_section = context_section,
+        thread_section = thread_section,
         from = from,
         subject = subject,
         body = body,
@@ -259,7 +339,7 @@ pub fn get_search_keywords_prompt(body: &str) -> String {
         Emika
 
         ## OUTPUT 1
-        Attendance,Attendance Report,Appearance,出勤,出社,勤怠
+        {{"documents": [{{"name": "Attendance Report", "keywords": ["Attendance", "Attendance Report", "Appearance", "出勤", "出社", "勤怠"]}}]}}
 
         ---
 
@@ -279,11 +359,11 @@ pub fn get_search_keywords_prompt(body: &str) -> String {
         単
 
         ## OUTPUT 2
-        Attendance,Attendance Report,Coverage,Coverage Report,Coverage,Coverage Plan,Appearance,出勤,出社,勤怠
+        {{"documents": [{{"name": "Attendance List", "keywords": ["Attendance", "Attendance Report", "Appearance", "出勤", "出社", "勤怠"]}}]}}
 
         ---
 
-        ## INPUT EMAIL 2
+        ## INPUT EMAIL 3
 
         From: Minzi <minzi@example.com>
         Subject: 【TPJP/DAWN】先週分の出勤表の共有について
@@ -300,16 +380,16 @@ pub fn get_search_keywords_prompt(body: &str) -> String {
         Minzi Shan
 
         ## OUTPUT 3
-        Attendance,Attendance Report,Coverage,Coverage Report,Coverage,Coverage Plan,Appearance,出勤,出社,勤怠
+        {{"documents": [{{"name": "Attendance Report", "keywords": ["Attendance", "Attendance Report", "Appearance", "出勤", "出社", "勤怠"]}}, {{"name": "Coverage Plan", "keywords": ["Coverage", "Coverage Report", "Coverage Plan"]}}]}}
 
         ---
 
         # INSTRUCTIONS
-        Analyze the INPUT EMAIL BODY and generate a comma-separated list of keywords based on the following rules:
-        1. **Identify the core request**: Extract the essential file name or topic (e.g., "Attendance Report").
-        2. **Generate Semantic Keywords**: Include conceptually related English words and synonyms (e.g., "Appearance").
-        3. **Generate Multilingual Keywords**: Include relevant Japanese translations and synonyms, as shown in the example (e.g., "出勤", "勤怠").
-        4. **Format the Output**: Combine all keywords into a single line, separated only by commas. Do not include labels, explanations, or any text other than the keywords themselves.
+        Analyze the INPUT EMAIL BODY and list every document it asks for, based on the following rules:
+        1. **Identify each requested document**: One entry per distinct file name or topic (e.g., "Attendance Report" and "Coverage Plan" are two documents).
+        2. **Generate Semantic Keywords**: For each document, include conceptually related English words and synonyms (e.g., "Appearance").
+        3. **Generate Multilingual Keywords**: Include relevant Japanese translations and synonyms, as shown in the examples (e.g., "出勤", "勤怠").
+        4. **Format the Output**: A single JSON object with a "documents" array; each entry has a short "name" and its "keywords". Do not include any other text.
 
         ---
 
@@ -318,3 +398,28 @@ pub fn get_search_keywords_prompt(body: &str) -> String {
         body
     )
 }
+
+/// JSON schema for the reply to `get_search_keywords_prompt`; mirrors
+/// `models::RequestedDocuments`.
+pub fn get_search_keywords_schema() -> serde_json::Value {
+    serde_json::json!({
+        "type": "object",
+        "properties": {
+            "documents": {
+                "type": "array",
+                "items": {
+                    "type": "object",
+                    "properties": {
+                        "name": { "type": "string" },
+                        "keywords": {
+                            "type": "array",
+                            "items": { "type": "string" }
+                        }
+                    },
+                    "required": ["name", "keywords"]
+                }
+            }
+        },
+        "required": ["documents"]
+    })
+}
@@ -0,0 +1,45 @@
+use super::client;
+use crate::error::ApiError;
+use crate::llm::LlmClient;
+use async_trait::async_trait;
+
+/// `LlmClient` backed by the Gemini API.
+pub struct GeminiClient {
+    api_key: String,
+    model: String,
+    embedding_model: String,
+}
+
+impl GeminiClient {
+    pub fn new(api_key: String, model: Option<String>, embedding_model: Option<String>) -> Self {
+        Self {
+            api_key,
+            model: model.unwrap_or_else(|| client::DEFAULT_MODEL.to_string()),
+            embedding_model: embedding_model
+                .unwrap_or_else(|| client::DEFAULT_EMBEDDING_MODEL.to_string()),
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl LlmClient for GeminiClient {
+    async fn generate(&self, prompt: &str) -> Result<String, ApiError> {
+        client::call_gemini(&self.api_key, &self.model, prompt, None).await
+    }
+
+    async fn generate_json(
+        &self,
+        prompt: &str,
+        schema: &serde_json::Value,
+    ) -> Result<String, ApiError> {
+        client::call_gemini(&self.api_key, &self.model, prompt, Some(schema)).await
+    }
+
+    async fn embed(&self, text: &str) -> Result<Vec<f32>, ApiError> {
+        client::get_embedding(&self.api_key, &self.embedding_model, text).await
+    }
+
+    fn embedding_model(&self) -> &str {
+        &self.embedding_model
+    }
+}
@@ -1,6 +1,10 @@
+use super::mime;
+use crate::error::{Api, ApiError};
 use crate::models::*;
+use crate::oauth::token::TokenManager;
 use base64::{engine::general_purpose::URL_SAFE, Engine as _};
-use worker::*;
+
+type Result<T> = std::result::Result<T, ApiError>;
 
 pub async fn get_access_token(
     client_id: &str,
@@ -20,52 +24,101 @@ pub async fn get_access_token(
         .form(&params)
         .send()
         .await
-        .map_err(|e| Error::from(format!("Reqwest error: {}", e)))?;
-
-    if !res.status().is_success() {
-        let error_text = res
-            .text()
-            .await
-            .unwrap_or_else(|_| "Unknown error".to_string());
-        return Err(Error::from(format!("Google API error: {}", error_text)));
-    }
+        .map_err(|e| ApiError::network(Api::OAuth, e))?;
+    let res = ApiError::check(Api::OAuth, res).await?;
 
     res.json::<GoogleTokenResponse>()
         .await
-        .map_err(|e| Error::from(format!("JSON parsing error: {}", e)))
+        .map_err(|e| ApiError::parse(Api::OAuth, format!("JSON parsing error: {}", e)))
 }
 
-pub async fn find_unread_emails(access_token: &str, user_id: &str) -> Result<Vec<MessageId>> {
+pub async fn exchange_authorization_code(
+    client_id: &str,
+    client_secret: &str,
+    code: &str,
+    code_verifier: &str,
+    redirect_uri: &str,
+) -> Result<AuthorizationCodeResponse> {
     let client = reqwest::Client::new();
-    let url = format!(
-        "https://gmail.googleapis.com/gmail/v1/users/{}/messages",
-        user_id
-    );
+    let params = [
+        ("client_id", client_id),
+        ("client_secret", client_secret),
+        ("code", code),
+        ("code_verifier", code_verifier),
+        ("redirect_uri", redirect_uri),
+        ("grant_type", "authorization_code"),
+    ];
 
     let res = client
-        .get(&url)
-        .bearer_auth(access_token)
-        .query(&[("q", "is:unread")])
+        .post("https://oauth2.googleapis.com/token")
+        .form(&params)
         .send()
         .await
-        .map_err(|e| Error::from(format!("Reqwest error: {}", e)))?;
+        .map_err(|e| ApiError::network(Api::OAuth, e))?;
+    let res = ApiError::check(Api::OAuth, res).await?;
 
-    let response_text = res
-        .text()
+    res.json::<AuthorizationCodeResponse>()
         .await
-        .map_err(|e| Error::from(format!("Failed to read response text: {}", e)))?;
-
-    match serde_json::from_str::<MessageListResponse>(&response_text) {
-        Ok(parse_data) => Ok(parse_data.messages.unwrap_or_default()),
-        Err(_) => Err(Error::from(format!(
-            "Gmail API returned non-JSON or error response: {}",
-            response_text
-        ))),
+        .map_err(|e| ApiError::parse(Api::OAuth, format!("JSON parsing error: {}", e)))
+}
+
+pub async fn get_profile(tokens: &TokenManager, user_id: &str) -> Result<Profile> {
+    let client = reqwest::Client::new();
+    let url = format!(
+        "https://gmail.googleapis.com/gmail/v1/users/{}/profile",
+        user_id
+    );
+
+    let res = tokens.send(Api::Gmail, client.get(&url)).await?;
+
+    let res = ApiError::check(Api::Gmail, res).await?;
+    res.json::<Profile>()
+        .await
+        .map_err(|e| ApiError::parse(Api::Gmail, format!("JSON parsing error: {}", e)))
+}
+
+/// Lists one page of unread messages without any of `exclude_labels`, at
+/// most `max_results` long, starting at `page_token` when resuming an earlier
+/// listing.
+pub async fn find_unread_emails(
+    tokens: &TokenManager,
+    user_id: &str,
+    exclude_labels: &[&str],
+    page_token: Option<&str>,
+    max_results: u32,
+) -> Result<MessageListResponse> {
+    let client = reqwest::Client::new();
+    let url = format!(
+        "https://gmail.googleapis.com/gmail/v1/users/{}/messages",
+        user_id
+    );
+
+    // Gmail search writes the `/` of nested label names as `-`.
+    let q = std::iter::once("is:unread".to_string())
+        .chain(
+            exclude_labels
+                .iter()
+                .map(|name| format!("-label:{}", name.replace(['/', ' '], "-"))),
+        )
+        .collect::<Vec<_>>()
+        .join(" ");
+    let mut query = vec![("q", q), ("maxResults", max_results.to_string())];
+    if let Some(token) = page_token {
+        query.push(("pageToken", token.to_string()));
     }
+
+    let res = tokens
+        .send(Api::Gmail, client.get(&url).query(&query))
+        .await?;
+
+    let res = ApiError::check(Api::Gmail, res).await?;
+    res.json::<MessageListResponse>()
+        .await
+        .map_err(|e| ApiError::parse(Api::Gmail, format!("JSON parsing error: {}", e)))
 }
 
 pub async fn get_email_details(
-    access_token: &str,
+    tokens: &TokenManager,
     user_id: &str,
     message_id: &str,
 ) -> Result<Message> {
@@ -75,93 +128,91 @@ pub async fn get_email_details(
         user_id, message_id
     );
 
-    let res = client
-        .get(&url)
-        .bearer_auth(access_token)
-        .query(&[
-            ("format", "full"),
-            ("metadataHeaders", "Date"),
-            ("metadataHeaders", "From"),
-            ("metadataHeaders", "To"),
-            ("metadataHeaders", "Cc"),
-            ("metadataHeaders", "Bcc"),
-            ("metadataHeaders", "Subject"),
-        ])
-        .send()
+    let res = tokens
+        .send(
+            Api::Gmail,
+            client.get(&url).query(&[
+                ("format", "full"),
+                ("metadataHeaders", "Date"),
+                ("metadataHeaders", "From"),
+                ("metadataHeaders", "To"),
+                ("metadataHeaders", "Cc"),
+                ("metadataHeaders", "Reply-To"),
+                ("metadataHeaders", "Bcc"),
+                ("metadataHeaders", "Subject"),
+                ("metadataHeaders", "Message-ID"),
+                ("metadataHeaders", "References"),
+            ]),
+        )
+        .await?;
+
+    let res = ApiError::check(Api::Gmail, res).await?;
+    res.json::<Message>()
         .await
-        .map_err(|e| Error::from(format!("Reqwest error: {}", e)))?;
+        .map_err(|e| ApiError::parse(Api::Gmail, format!("JSON parsing error: {}", e)))
+}
+
+/// Fetches every message of `thread_id` with its full payload.
+pub async fn get_thread(tokens: &TokenManager, user_id: &str, thread_id: &str) -> Result<Thread> {
+    let client = reqwest::Client::new();
+    let url = format!(
+        "https://gmail.googleapis.com/gmail/v1/users/{}/threads/{}",
+        user_id, thread_id
+    );
+
+    let res = tokens
+        .send(Api::Gmail, client.get(&url).query(&[("format", "full")]))
+        .await?;
 
-    let response_text = res
-        .text()
+    let res = ApiError::check(Api::Gmail, res).await?;
+    res.json::<Thread>()
         .await
-        .map_err(|e| Error::from(format!("Failed to read response text: {}", e)))?;
-
-    match serde_json::from_str::<Message>(&response_text) {
-        Ok(parsed_text) => Ok(parsed_text),
-        Err(_) => Err(Error::from(format!(
-            "Gmail API returned non-JSON or error response: {}",
-            response_text
-        ))),
-    }
+        .map_err(|e| ApiError::parse(Api::Gmail, format!("JSON parsing error: {}", e)))
 }
 
+/// Creates a reply draft in `thread_id`, sent from the `from` alias if given.
+/// `subject` is used as is, so it should already carry its reply prefix.
+/// With an `html_body` the draft is `multipart/alternative`, `body` being the
+/// plain-text version.
+/// `in_reply_to` and `references` are the original message's `Message-ID`
+/// and `References` headers, used so the reply also threads outside Gmail.
+#[allow(clippy::too_many_arguments)]
 pub async fn create_draft_with_attachment(
-    access_token: &str,
+    tokens: &TokenManager,
     user_id: &str,
     thread_id: &str,
+    from: Option<&str>,
     to_all: &str,
     cc_all: &str,
     subject: &str,
     body: &str,
-    attachment: Option<Attachment>,
-) -> Result<()> {
-    let raw_email = if let Some(att) = attachment {
-        let boundary = "boundary_string_for_email_draft_bot";
-        let mut headers = format!("To: {}\r\n", to_all);
-        if !cc_all.is_empty() {
-            headers.push_str(&format!("Cc: {}\r\n", cc_all));
-        }
-        headers.push_str(&format!("Subject: Re: {}\r\n", subject));
-        headers.push_str("MIME-Version: 1.0\r\n");
-        headers.push_str(&format!(
-            "Content-Type: multipart/mixed; boundary=\"{}\"\r\n",
-            boundary
-        ));
-
-        let body_part = format!(
-            "--{boundary}\r\n\
-             Content-Type: text/plain; charset=\"UTF-8\"\r\n\r\n\
-             {body}\r\n",
-            boundary = boundary,
-            body = body
-        );
-
-        let attachment_part = format!(
-            "--{boundary}\r\n\
-             Content-Type: {mime_type}; name=\"{filename}\"\r\n\
-             Content-Disposition: attachment; filename=\"{filename}\"\r\n\
-             Content-Transfer-Encoding: base64\r\n\r\n\
-             {data}\r\n",
-            boundary = boundary,
-            mime_type = att.mime_type,
-            filename = att.filename,
-            data = base64::engine::general_purpose::STANDARD.encode(&att.data)
-        );
-
-        format!(
-            "{headers}\r\n{body_part}{attachment_part}--{boundary}--\r\n",
-            headers = headers,
-            body_part = body_part,
-            attachment_part = attachment_part,
-            boundary = boundary
-        )
-    } else {
-        let mut headers = format!("To: {}\r\nSubject: Re: {}\r\n", to_all, subject);
-        if !cc_all.is_empty() {
-            headers.push_str(&format!("Cc: {}\r\n", cc_all));
-        }
-        format!("{}\r\n{}", headers, body)
-    };
+    html_body: Option<&str>,
+    attachments: &[Attachment],
+    in_reply_to: Option<&str>,
+    references: Option<&str>,
+) -> Result<Draft> {
+    let mut headers = String::new();
+    if let Some(from) = from {
+        headers.push_str(&format!("From: {}\r\n", mime::encode_address_list(from)));
+    }
+    headers.push_str(&format!("To: {}\r\n", mime::encode_address_list(to_all)));
+    if !cc_all.is_empty() {
+        headers.push_str(&format!("Cc: {}\r\n", mime::encode_address_list(cc_all)));
+    }
+    headers.push_str(&format!(
+        "Subject: {}\r\n",
+        mime::encode_header_value(subject)
+    ));
+    if let Some(message_id) = in_reply_to {
+        headers.push_str(&mime::reply_headers(message_id, references));
+    }
+    headers.push_str("MIME-Version: 1.0\r\n");
+
+    let raw_email = format!(
+        "{}{}",
+        headers,
+        mime::draft_entity(body, html_body, attachments)
+    );
 
     let encoded_email = URL_SAFE.encode(raw_email);
 
@@ -178,29 +229,23 @@ pub async fn create_draft_with_attachment(
         user_id
     );
 
-    let res = client
-        .post(&url)
-        .bearer_auth(access_token)
-        .json(&draft_request)
-        .send()
-        .await
-        .map_err(|e| Error::from(format!("Reqwest error: {}", e)))?;
+    let res = tokens
+        .send(Api::Gmail, client.post(&url).json(&draft_request))
+        .await?;
 
-    if res.status().is_success() {
-        Ok(())
-    } else {
-        let error_text = res
-            .text()
-            .await
-            .unwrap_or_else(|_| "Unknown error".to_string());
-        Err(Error::from(format!(
-            "Failed to create draft: {}",
-            error_text
-        )))
-    }
+    let res = ApiError::check(Api::Gmail, res).await?;
+    res.json::<Draft>()
+        .await
+        .map_err(|e| ApiError::parse(Api::Gmail, format!("JSON parsing error: {}", e)))
 }
 
-pub async fn mark_as_read(access_token: &str, user_id: &str, message_id: &str) -> Result<()> {
+pub async fn modify_labels(
+    tokens: &TokenManager,
+    user_id: &str,
+    message_id: &str,
+    add_label_ids: &[&str],
+    remove_label_ids: &[&str],
+) -> Result<()> {
     let client = reqwest::Client::new();
     let url = format!(
         "https://gmail.googleapis.com/gmail/v1/users/{}/messages/{}/modify",
@@ -208,27 +253,158 @@ pub async fn mark_as_read(access_token: &str, user_id: &str, message_id: &str) -
     );
 
     let modify_request = ModifyMessageRequest {
-        remove_label_ids: vec!["UNREAD".to_string()],
+        add_label_ids: add_label_ids.iter().map(|l| l.to_string()).collect(),
+        remove_label_ids: remove_label_ids.iter().map(|l| l.to_string()).collect(),
     };
 
-    let res = client
-        .post(&url)
-        .bearer_auth(access_token)
-        .json(&modify_request)
-        .send()
+    let res = tokens
+        .send(Api::Gmail, client.post(&url).json(&modify_request))
+        .await?;
+
+    ApiError::check(Api::Gmail, res).await?;
+    Ok(())
+}
+
+pub async fn list_send_as(tokens: &TokenManager, user_id: &str) -> Result<Vec<SendAs>> {
+    let client = reqwest::Client::new();
+    let url = format!(
+        "https://gmail.googleapis.com/gmail/v1/users/{}/settings/sendAs",
+        user_id
+    );
+
+    let res = tokens.send(Api::Gmail, client.get(&url)).await?;
+
+    let res = ApiError::check(Api::Gmail, res).await?;
+    let list = res
+        .json::<SendAsListResponse>()
         .await
-        .map_err(|e| Error::from(format!("Reqwest error: {}", e)))?;
+        .map_err(|e| ApiError::parse(Api::Gmail, format!("JSON parsing error: {}", e)))?;
+    Ok(list.send_as.unwrap_or_default())
+}
+
+pub async fn list_labels(tokens: &TokenManager, user_id: &str) -> Result<Vec<Label>> {
+    let client = reqwest::Client::new();
+    let url = format!(
+        "https://gmail.googleapis.com/gmail/v1/users/{}/labels",
+        user_id
+    );
+
+    let res = tokens.send(Api::Gmail, client.get(&url)).await?;
+
+    let res = ApiError::check(Api::Gmail, res).await?;
+    let list = res
+        .json::<LabelListResponse>()
+        .await
+        .map_err(|e| ApiError::parse(Api::Gmail, format!("JSON parsing error: {}", e)))?;
+    Ok(list.labels.unwrap_or_default())
+}
+
+pub async fn create_label(tokens: &TokenManager, user_id: &str, name: &str) -> Result<Label> {
+    let client = reqwest::Client::new();
+    let url = format!(
+        "https://gmail.googleapis.com/gmail/v1/users/{}/labels",
+        user_id
+    );
 
-    if res.status().is_success() {
-        Ok(())
-    } else {
-        let error_text = res
-            .text()
+    let create_request = CreateLabelRequest {
+        name: name.to_string(),
+        label_list_visibility: "labelShow".to_string(),
+        message_list_visibility: "show".to_string(),
+    };
+
+    let res = tokens
+        .send(Api::Gmail, client.post(&url).json(&create_request))
+        .await?;
+
+    let res = ApiError::check(Api::Gmail, res).await?;
+    res.json::<Label>()
+        .await
+        .map_err(|e| ApiError::parse(Api::Gmail, format!("JSON parsing error: {}", e)))
+}
+
+pub async fn watch_mailbox(
+    tokens: &TokenManager,
+    user_id: &str,
+    topic_name: &str,
+) -> Result<WatchResponse> {
+    let client = reqwest::Client::new();
+    let url = format!(
+        "https://gmail.googleapis.com/gmail/v1/users/{}/watch",
+        user_id
+    );
+
+    let watch_request = WatchRequest {
+        topic_name: topic_name.to_string(),
+        label_ids: vec!["INBOX".to_string()],
+        label_filter_behavior: "include".to_string(),
+    };
+
+    let res = tokens
+        .send(Api::Gmail, client.post(&url).json(&watch_request))
+        .await?;
+
+    let res = ApiError::check(Api::Gmail, res).await?;
+    res.json::<WatchResponse>()
+        .await
+        .map_err(|e| ApiError::parse(Api::Gmail, format!("JSON parsing error: {}", e)))
+}
+
+/// Returns the messages added to the mailbox since `start_history_id`,
+/// following `nextPageToken` until the history is exhausted, together with
+/// the mailbox's current history ID to use as the next checkpoint.
+pub async fn list_history(
+    tokens: &TokenManager,
+    user_id: &str,
+    start_history_id: &str,
+) -> Result<(Vec<MessageId>, String)> {
+    let client = reqwest::Client::new();
+    let url = format!(
+        "https://gmail.googleapis.com/gmail/v1/users/{}/history",
+        user_id
+    );
+
+    let mut messages: Vec<MessageId> = Vec::new();
+    let mut page_token: Option<String> = None;
+
+    loop {
+        let mut query = vec![
+            ("startHistoryId", start_history_id.to_string()),
+            ("historyTypes", "messageAdded".to_string()),
+            ("labelId", "INBOX".to_string()),
+        ];
+        if let Some(token) = &page_token {
+            query.push(("pageToken", token.clone()));
+        }
+
+        let res = tokens
+            .send(Api::Gmail, client.get(&url).query(&query))
+            .await?;
+
+        let res = ApiError::check(Api::Gmail, res).await?;
+        let page = res
+            .json::<HistoryListResponse>()
             .await
-            .unwrap_or_else(|_| "Unknown error".to_string());
-        Err(Error::from(format!(
-            "Failed to modify message: {}",
-            error_text
-        )))
+            .map_err(|e| ApiError::parse(Api::Gmail, format!("JSON parsing error: {}", e)))?;
+
+        for record in page.history.unwrap_or_default() {
+            for added in record.messages_added.unwrap_or_default() {
+                let is_unread = added
+                    .message
+                    .label_ids
+                    .as_ref()
+                    .is_some_and(|labels| labels.iter().any(|l| l == "UNREAD"));
+                if is_unread && !messages.iter().any(|m| m.id == added.message.id) {
+                    messages.push(MessageId {
+                        id: added.message.id,
+                        thread_id: added.message.thread_id,
+                    });
+                }
+            }
+        }
+
+        match page.next_page_token {
+            Some(token) => page_token = Some(token),
+            None => return Ok((messages, page.history_id)),
+        }
     }
 }
@@ -0,0 +1,491 @@
+//! Header encoding and body structure of the raw MIME messages `client`
+//! builds for drafts.
+
+use crate::mail::address::parse_address_list;
+use crate::models::Attachment;
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+
+/// Bytes of UTF-8 per RFC 2047 encoded-word; 45 bytes encode to 60 base64
+/// characters, keeping each word within the 75-character limit.
+const ENCODED_WORD_BYTES: usize = 45;
+/// Line length RFC 5322 recommends; folded headers are kept within it where
+/// their words allow.
+const MAX_LINE_LENGTH: usize = 78;
+/// Boundaries of the draft's multipart entities. Base64 never contains `_`,
+/// so they cannot occur inside a part.
+const ALTERNATIVE_BOUNDARY: &str = "alternative_boundary_for_email_draft_bot";
+const MIXED_BOUNDARY: &str = "boundary_string_for_email_draft_bot";
+
+/// Removes CR and LF so a value taken from an email or a file name cannot
+/// end the header early and inject headers of its own.
+pub fn strip_line_breaks(value: &str) -> String {
+    value
+        .split(['\r', '\n'])
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Encodes an unstructured header value such as `Subject`. ASCII values are
+/// kept as they are; anything else becomes RFC 2047 `=?UTF-8?B?...?=` words,
+/// folded onto continuation lines.
+pub fn encode_header_value(value: &str) -> String {
+    let value = strip_line_breaks(value);
+    if value.is_ascii() {
+        return value;
+    }
+    encoded_words(&value).join("\r\n ")
+}
+
+/// Encodes an address list such as `To` or `Cc`. Non-ASCII display names
+/// (`山田 太郎 <yamada@example.com>`) become RFC 2047 words; addresses and
+/// ASCII names are kept as they are.
+pub fn encode_address_list(value: &str) -> String {
+    let value = strip_line_breaks(value);
+    if value.is_ascii() {
+        return value;
+    }
+    parse_address_list(&value)
+        .into_iter()
+        .map(|address| match &address.name {
+            Some(name) if !name.is_ascii() => {
+                format!("{} <{}>", encoded_words(name).join(" "), address.email)
+            }
+            _ => address.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// The `In-Reply-To` and `References` headers of a reply to the message with
+/// ID `in_reply_to`, whose own `References` header is `references`. The new
+/// `References` are the original's followed by the original itself, each ID
+/// once, folded between IDs so that long threads keep within the line limit.
+pub fn reply_headers(in_reply_to: &str, references: Option<&str>) -> String {
+    let message_id = strip_line_breaks(in_reply_to).trim().to_string();
+    let references = references.map(strip_line_breaks).unwrap_or_default();
+
+    let mut ids: Vec<&str> = Vec::new();
+    for id in references
+        .split_whitespace()
+        .chain(std::iter::once(message_id.as_str()))
+    {
+        if !ids.contains(&id) {
+            ids.push(id);
+        }
+    }
+
+    let mut header = String::from("References:");
+    let mut line_length = header.len();
+    for (i, id) in ids.iter().enumerate() {
+        if i > 0 && line_length + 1 + id.len() > MAX_LINE_LENGTH {
+            header.push_str("\r\n");
+            line_length = 0;
+        }
+        header.push(' ');
+        header.push_str(id);
+        line_length += 1 + id.len();
+    }
+
+    format!("In-Reply-To: {}\r\n{}\r\n", message_id, header)
+}
+
+/// The `Content-Type` and `Content-Disposition` headers of an attachment.
+/// Non-ASCII names use RFC 2231 `filename*=` with an ASCII fallback in
+/// `filename=`, plus an encoded-word `name=` for clients that only read that.
+fn attachment_headers(mime_type: &str, filename: &str) -> String {
+    let filename = strip_line_breaks(filename);
+    let mime_type = strip_line_breaks(mime_type);
+
+    if filename.is_ascii() {
+        let quoted = quote(&filename);
+        return format!(
+            "Content-Type: {mime_type}; name={quoted}\r\n\
+             Content-Disposition: attachment; filename={quoted}\r\n",
+            mime_type = mime_type,
+            quoted = quoted
+        );
+    }
+
+    let fallback: String = filename
+        .chars()
+        .map(|c| if c.is_ascii() { c } else { '_' })
+        .collect();
+    format!(
+        "Content-Type: {mime_type}; name=\"{name}\"\r\n\
+         Content-Disposition: attachment; filename={fallback};\r\n filename*=UTF-8''{encoded}\r\n",
+        mime_type = mime_type,
+        name = encoded_words(&filename).join(" "),
+        fallback = quote(&fallback),
+        encoded = percent_encode(&filename)
+    )
+}
+
+/// The body of a draft, starting with its `Content-Type` header: `body` as
+/// `text/plain`, or with an `html_body` both as `multipart/alternative`.
+/// With `attachments` that entity becomes the first part of a
+/// `multipart/mixed`, followed by one part per attachment.
+pub fn draft_entity(body: &str, html_body: Option<&str>, attachments: &[Attachment]) -> String {
+    let body_entity = match html_body {
+        Some(html) => format!(
+            "Content-Type: multipart/alternative; boundary=\"{boundary}\"\r\n\r\n\
+             --{boundary}\r\n{plain}--{boundary}\r\n{html}--{boundary}--\r\n",
+            boundary = ALTERNATIVE_BOUNDARY,
+            plain = text_part("text/plain", body),
+            html = text_part("text/html", html)
+        ),
+        None => text_part("text/plain", body),
+    };
+    if attachments.is_empty() {
+        return body_entity;
+    }
+
+    let attachment_parts: String = attachments
+        .iter()
+        .map(|att| {
+            format!(
+                "--{boundary}\r\n\
+                 {attachment_headers}\
+                 Content-Transfer-Encoding: base64\r\n\r\n\
+                 {data}\r\n",
+                boundary = MIXED_BOUNDARY,
+                attachment_headers = attachment_headers(&att.mime_type, &att.filename),
+                data = wrap_base64(&att.data)
+            )
+        })
+        .collect();
+    format!(
+        "Content-Type: multipart/mixed; boundary=\"{boundary}\"\r\n\r\n\
+         --{boundary}\r\n{body_entity}{attachment_parts}--{boundary}--\r\n",
+        boundary = MIXED_BOUNDARY,
+        body_entity = body_entity,
+        attachment_parts = attachment_parts
+    )
+}
+
+/// A UTF-8 text part: its `Content-Type` and transfer-encoding headers, a
+/// blank line and the base64-encoded text.
+fn text_part(mime_type: &str, text: &str) -> String {
+    format!(
+        "Content-Type: {}; charset=\"UTF-8\"\r\n\
+         Content-Transfer-Encoding: base64\r\n\r\n\
+         {}\r\n",
+        mime_type,
+        wrap_base64(text.as_bytes())
+    )
+}
+
+/// Base64 broken into 76-character lines, as RFC 2045 requires.
+fn wrap_base64(data: &[u8]) -> String {
+    let encoded = STANDARD.encode(data);
+    encoded
+        .as_bytes()
+        .chunks(76)
+        .map(|line| std::str::from_utf8(line).unwrap_or_default())
+        .collect::<Vec<_>>()
+        .join("\r\n")
+}
+
+/// Splits `value` on character boundaries into base64 encoded-words.
+fn encoded_words(value: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut chunk = String::new();
+    for c in value.chars() {
+        if chunk.len() + c.len_utf8() > ENCODED_WORD_BYTES {
+            words.push(format!("=?UTF-8?B?{}?=", STANDARD.encode(&chunk)));
+            chunk.clear();
+        }
+        chunk.push(c);
+    }
+    if !chunk.is_empty() {
+        words.push(format!("=?UTF-8?B?{}?=", STANDARD.encode(&chunk)));
+    }
+    words
+}
+
+/// A MIME quoted-string: wrapped in double quotes, with `\` and `"` escaped.
+fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// RFC 2231 value encoding: attribute characters as is, every other byte as
+/// `%XX`.
+fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z'
+            | b'a'..=b'z'
+            | b'0'..=b'9'
+            | b'!'
+            | b'#'
+            | b'$'
+            | b'&'
+            | b'+'
+            | b'-'
+            | b'.'
+            | b'^'
+            | b'_'
+            | b'`'
+            | b'|'
+            | b'~' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn address_list_encodes_only_non_ascii_names() {
+        assert_eq!(
+            encode_address_list(r#""Doe, Jane" <jane@x.com>, bob@x.com"#),
+            r#""Doe, Jane" <jane@x.com>, bob@x.com"#
+        );
+        assert_eq!(
+            encode_address_list("山田 太郎 <yamada@example.jp>, Bob <bob@x.com>"),
+            format!(
+                "=?UTF-8?B?{}?= <yamada@example.jp>, Bob <bob@x.com>",
+                STANDARD.encode("山田 太郎")
+            )
+        );
+    }
+
+    #[test]
+    fn address_list_strips_line_breaks() {
+        assert_eq!(
+            encode_address_list("jane@x.com\r\nBcc: evil@x.com"),
+            "jane@x.com Bcc: evil@x.com"
+        );
+    }
+
+    #[test]
+    fn references_end_with_the_parent_message() {
+        assert_eq!(
+            reply_headers("<b@x.com>", Some("<a@x.com>")),
+            "In-Reply-To: <b@x.com>\r\nReferences: <a@x.com> <b@x.com>\r\n"
+        );
+        assert_eq!(
+            reply_headers("<a@x.com>", None),
+            "In-Reply-To: <a@x.com>\r\nReferences: <a@x.com>\r\n"
+        );
+    }
+
+    #[test]
+    fn references_list_each_message_once() {
+        assert_eq!(
+            reply_headers("<b@x.com>", Some("<a@x.com> <b@x.com>\r\n <a@x.com>")),
+            "In-Reply-To: <b@x.com>\r\nReferences: <a@x.com> <b@x.com>\r\n"
+        );
+    }
+
+    #[test]
+    fn reply_headers_cannot_inject_headers() {
+        let headers = reply_headers(
+            "<b@x.com>\r\nBcc: evil@x.com",
+            Some("<a@x.com>\nBcc: evil@x.com"),
+        );
+
+        assert_eq!(headers.matches("\r\n").count(), 2);
+        assert!(headers.starts_with("In-Reply-To: <b@x.com> Bcc: evil@x.com\r\n"));
+    }
+
+    #[test]
+    fn long_references_are_folded_between_ids() {
+        let ids: Vec<String> = (0..40)
+            .map(|i| format!("<CAF{:04}abcdefghijklmnop@mail.gmail.com>", i))
+            .collect();
+        let headers = reply_headers("<parent@x.com>", Some(&ids.join(" ")));
+
+        let references = headers.split_once("References:").unwrap().1;
+        let lines: Vec<&str> = references.trim_end_matches("\r\n").split("\r\n").collect();
+        assert!(lines.len() > 1);
+        assert!(lines.iter().all(|line| line.len() <= MAX_LINE_LENGTH));
+        assert!(lines[1..].iter().all(|line| line.starts_with(' ')));
+        let unfolded: Vec<&str> = references.split_whitespace().collect();
+        assert_eq!(unfolded.len(), 41);
+        assert_eq!(
+            unfolded[..40],
+            ids.iter().map(String::as_str).collect::<Vec<_>>()
+        );
+        assert_eq!(unfolded[40], "<parent@x.com>");
+    }
+
+    /// Decodes a value made of `=?UTF-8?B?...?=` words separated by folding
+    /// whitespace.
+    fn decode_words(value: &str) -> String {
+        let bytes: Vec<u8> = value
+            .split_whitespace()
+            .flat_map(|word| {
+                let encoded = word
+                    .strip_prefix("=?UTF-8?B?")
+                    .and_then(|w| w.strip_suffix("?="))
+                    .expect("encoded word");
+                STANDARD.decode(encoded).unwrap()
+            })
+            .collect();
+        String::from_utf8(bytes).unwrap()
+    }
+
+    #[test]
+    fn ascii_subject_is_kept() {
+        assert_eq!(encode_header_value("Re: Invoice #42"), "Re: Invoice #42");
+    }
+
+    #[test]
+    fn non_ascii_subject_is_encoded_and_folded() {
+        let subject = "Re: 【TPJP/DAWN】来週の打ち合わせについてのご相談とお見積もりのお願い";
+        let encoded = encode_header_value(subject);
+
+        let lines: Vec<&str> = encoded.split("\r\n").collect();
+        assert!(lines.len() > 1);
+        assert!(lines.iter().all(|line| line.trim().len() <= 75));
+        assert!(lines[1..].iter().all(|line| line.starts_with(' ')));
+        assert_eq!(decode_words(&encoded), subject);
+    }
+
+    #[test]
+    fn subject_cannot_inject_headers() {
+        assert_eq!(
+            encode_header_value("Hello\r\nBcc: evil@x.com"),
+            "Hello Bcc: evil@x.com"
+        );
+        let encoded = encode_header_value("こんにちは\r\nBcc: evil@x.com");
+        assert!(!encoded.contains("\r\nBcc"));
+        assert_eq!(decode_words(&encoded), "こんにちは Bcc: evil@x.com");
+    }
+
+    #[test]
+    fn ascii_file_name_is_quoted() {
+        assert_eq!(
+            attachment_headers("application/pdf", r#"Q3 "final" report.pdf"#),
+            "Content-Type: application/pdf; name=\"Q3 \\\"final\\\" report.pdf\"\r\n\
+             Content-Disposition: attachment; filename=\"Q3 \\\"final\\\" report.pdf\"\r\n"
+        );
+    }
+
+    #[test]
+    fn non_ascii_file_name_has_encoded_and_fallback_names() {
+        let headers = attachment_headers("application/pdf", "見積書 2024.pdf");
+
+        assert!(headers.contains(&format!(
+            "name=\"=?UTF-8?B?{}?=\"",
+            STANDARD.encode("見積書 2024.pdf")
+        )));
+        assert!(headers.contains("filename=\"___ 2024.pdf\";"));
+        assert!(headers.contains("filename*=UTF-8''%E8%A6%8B%E7%A9%8D%E6%9B%B8%202024.pdf\r\n"));
+    }
+
+    #[test]
+    fn file_name_cannot_inject_headers() {
+        let headers = attachment_headers(
+            "text/plain",
+            "notes.txt\"\r\nContent-Type: text/html\r\n\r\n<script>",
+        );
+
+        assert_eq!(headers.matches("\r\n").count(), 2);
+        assert!(headers.contains("filename=\"notes.txt\\\" Content-Type: text/html <script>\"\r\n"));
+    }
+
+    /// Splits a MIME entity into its headers and its body.
+    fn split_entity(entity: &str) -> (&str, &str) {
+        entity
+            .split_once("\r\n\r\n")
+            .expect("blank line after the headers")
+    }
+
+    /// The parts of a multipart body delimited by `boundary`.
+    fn parts<'a>(body: &'a str, boundary: &str) -> Vec<&'a str> {
+        let delimiter = format!("--{}\r\n", boundary);
+        let body = body
+            .strip_prefix(&delimiter)
+            .and_then(|body| body.strip_suffix(&format!("--{}--\r\n", boundary)))
+            .expect("opening and closing boundaries");
+        body.split(&delimiter).collect()
+    }
+
+    /// The decoded content of a base64 part.
+    fn decode_part(part: &str) -> Vec<u8> {
+        let (_, body) = split_entity(part);
+        STANDARD.decode(body.replace("\r\n", "")).unwrap()
+    }
+
+    #[test]
+    fn plain_draft_is_a_single_text_part() {
+        let entity = draft_entity("Hello", None, &[]);
+
+        let (headers, _) = split_entity(&entity);
+        assert!(headers.starts_with("Content-Type: text/plain; charset=\"UTF-8\"\r\n"));
+        assert_eq!(decode_part(&entity), b"Hello");
+    }
+
+    #[test]
+    fn html_draft_has_plain_then_html_alternatives() {
+        let entity = draft_entity("Hello", Some("<p>Hello</p>"), &[]);
+
+        let (headers, body) = split_entity(&entity);
+        assert_eq!(
+            headers,
+            format!(
+                "Content-Type: multipart/alternative; boundary=\"{}\"",
+                ALTERNATIVE_BOUNDARY
+            )
+        );
+        let parts = parts(body, ALTERNATIVE_BOUNDARY);
+        assert_eq!(parts.len(), 2);
+        assert!(parts[0].starts_with("Content-Type: text/plain;"));
+        assert_eq!(decode_part(parts[0]), b"Hello");
+        assert!(parts[1].starts_with("Content-Type: text/html;"));
+        assert_eq!(decode_part(parts[1]), b"<p>Hello</p>");
+    }
+
+    #[test]
+    fn attachments_follow_the_text_in_multipart_mixed() {
+        let report: Vec<u8> = (0..=255).cycle().take(1000).collect();
+        let attachments = [
+            Attachment {
+                filename: "report.pdf".to_string(),
+                mime_type: "application/pdf".to_string(),
+                data: report.clone(),
+            },
+            Attachment {
+                filename: "見積書.csv".to_string(),
+                mime_type: "text/csv".to_string(),
+                data: b"item,price".to_vec(),
+            },
+        ];
+        let entity = draft_entity("Hello", Some("<p>Hello</p>"), &attachments);
+
+        let (headers, body) = split_entity(&entity);
+        assert_eq!(
+            headers,
+            format!(
+                "Content-Type: multipart/mixed; boundary=\"{}\"",
+                MIXED_BOUNDARY
+            )
+        );
+        let mixed = parts(body, MIXED_BOUNDARY);
+        assert_eq!(mixed.len(), 3);
+
+        let (text_headers, text_body) = split_entity(mixed[0]);
+        assert!(text_headers.starts_with("Content-Type: multipart/alternative;"));
+        assert_eq!(parts(text_body, ALTERNATIVE_BOUNDARY).len(), 2);
+
+        assert!(mixed[1].starts_with(
+            "Content-Type: application/pdf; name=\"report.pdf\"\r\n\
+             Content-Disposition: attachment; filename=\"report.pdf\"\r\n\
+             Content-Transfer-Encoding: base64\r\n\r\n"
+        ));
+        assert!(split_entity(mixed[1])
+            .1
+            .lines()
+            .all(|line| line.len() <= 76));
+        assert_eq!(decode_part(mixed[1]), report);
+
+        assert!(mixed[2].starts_with("Content-Type: text/csv; name=\"=?UTF-8?B?"));
+        assert!(mixed[2].contains("filename*=UTF-8''%E8%A6%8B%E7%A9%8D%E6%9B%B8.csv\r\n"));
+        assert_eq!(decode_part(mixed[2]), b"item,price");
+    }
+}
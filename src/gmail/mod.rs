@@ -1 +1,3 @@
 pub mod client;
+pub mod mime;
+pub mod provider;
@@ -0,0 +1,141 @@
+use super::client;
+use crate::error::ApiError;
+use crate::mail::MailProvider;
+use crate::models::{
+    BotLabel, DraftReply, Message, MessageId, MessageListResponse, SendAs, WatchResponse,
+};
+use crate::oauth::token::TokenManager;
+use async_trait::async_trait;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// `MailProvider` backed by the Gmail API for a single mailbox.
+pub struct GmailProvider {
+    tokens: Rc<TokenManager>,
+    user_id: String,
+    /// Label IDs by name, filled on the first `label_id` lookup.
+    labels: RefCell<HashMap<String, String>>,
+}
+
+impl GmailProvider {
+    pub fn new(tokens: Rc<TokenManager>, user_id: String) -> Self {
+        Self {
+            tokens,
+            user_id,
+            labels: RefCell::new(HashMap::new()),
+        }
+    }
+
+    async fn refresh_labels(&self) -> Result<(), ApiError> {
+        let labels = client::list_labels(&self.tokens, &self.user_id).await?;
+        *self.labels.borrow_mut() = labels.into_iter().map(|l| (l.name, l.id)).collect();
+        Ok(())
+    }
+
+    fn cached_label_id(&self, name: &str) -> Option<String> {
+        self.labels.borrow().get(name).cloned()
+    }
+}
+
+#[async_trait(?Send)]
+impl MailProvider for GmailProvider {
+    async fn list_unread(
+        &self,
+        page_token: Option<&str>,
+        max_results: u32,
+    ) -> Result<MessageListResponse, ApiError> {
+        let done: Vec<&str> = BotLabel::ALL
+            .iter()
+            .filter(|label| label.is_final())
+            .map(|label| label.name())
+            .collect();
+        client::find_unread_emails(&self.tokens, &self.user_id, &done, page_token, max_results)
+            .await
+    }
+
+    async fn get_message(&self, message_id: &str) -> Result<Message, ApiError> {
+        client::get_email_details(&self.tokens, &self.user_id, message_id).await
+    }
+
+    async fn get_thread(&self, thread_id: &str) -> Result<Vec<Message>, ApiError> {
+        Ok(client::get_thread(&self.tokens, &self.user_id, thread_id)
+            .await?
+            .messages)
+    }
+
+    async fn create_draft(&self, draft: &DraftReply) -> Result<String, ApiError> {
+        let created = client::create_draft_with_attachment(
+            &self.tokens,
+            &self.user_id,
+            &draft.thread_id,
+            draft.from.as_deref(),
+            &draft.to,
+            &draft.cc,
+            &draft.subject,
+            &draft.body,
+            draft.html_body.as_deref(),
+            &draft.attachments,
+            draft.in_reply_to.as_deref(),
+            draft.references.as_deref(),
+        )
+        .await?;
+        Ok(created.id)
+    }
+
+    async fn list_send_as(&self) -> Result<Vec<SendAs>, ApiError> {
+        client::list_send_as(&self.tokens, &self.user_id).await
+    }
+
+    async fn label_id(&self, name: &str) -> Result<String, ApiError> {
+        if let Some(id) = self.cached_label_id(name) {
+            return Ok(id);
+        }
+        self.refresh_labels().await?;
+        if let Some(id) = self.cached_label_id(name) {
+            return Ok(id);
+        }
+
+        match client::create_label(&self.tokens, &self.user_id, name).await {
+            Ok(label) => {
+                self.labels
+                    .borrow_mut()
+                    .insert(label.name, label.id.clone());
+                Ok(label.id)
+            }
+            // Another run created it in the meantime.
+            Err(e) if e.status == Some(409) => {
+                self.refresh_labels().await?;
+                self.cached_label_id(name).ok_or(e)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn modify_labels(
+        &self,
+        message_id: &str,
+        add_label_ids: &[&str],
+        remove_label_ids: &[&str],
+    ) -> Result<(), ApiError> {
+        client::modify_labels(
+            &self.tokens,
+            &self.user_id,
+            message_id,
+            add_label_ids,
+            remove_label_ids,
+        )
+        .await
+    }
+
+    async fn list_history(
+        &self,
+        start_history_id: &str,
+    ) -> Result<(Vec<MessageId>, String), ApiError> {
+        client::list_history(&self.tokens, &self.user_id, start_history_id).await
+    }
+
+    async fn watch(&self, topic_name: &str) -> Result<WatchResponse, ApiError> {
+        client::watch_mailbox(&self.tokens, &self.user_id, topic_name).await
+    }
+}
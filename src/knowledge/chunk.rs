@@ -0,0 +1,211 @@
+//! Splits ingested text into chunks small enough to embed and to quote in
+//! the drafting prompt.
+
+/// Characters after which a long run of text without spaces (Japanese, for
+/// instance) is preferably split.
+const SENTENCE_ENDS: [char; 6] = ['。', '！', '？', '.', '!', '?'];
+
+/// Splits `text` into chunks of at most `max_chars` characters along
+/// paragraph boundaries; longer paragraphs are split between words, or
+/// after a sentence for scripts without spaces. In Markdown every heading
+/// starts a new chunk, and the headings above a chunk are repeated at its
+/// top so that it still says what it is about when read on its own.
+pub fn split(text: &str, markdown: bool, max_chars: usize) -> Vec<String> {
+    let mut chunks = Vec::new();
+    let mut headings: Vec<(usize, String)> = Vec::new();
+    let mut current = String::new();
+
+    for paragraph in paragraphs(text, markdown) {
+        let mut body = paragraph.as_str();
+        if markdown && body.starts_with('#') {
+            push_chunk(&mut chunks, &headings, &mut current);
+            let (line, rest) = body.split_once('\n').unwrap_or((body, ""));
+            let level = line.chars().take_while(|&c| c == '#').count();
+            headings.retain(|(other, _)| *other < level);
+            headings.push((level, line.trim_start_matches('#').trim().to_string()));
+            body = rest.trim();
+            if body.is_empty() {
+                continue;
+            }
+        }
+
+        let budget = max_chars
+            .saturating_sub(heading_line(&headings).map_or(0, |h| char_len(&h) + 2))
+            .max(1);
+        for piece in split_long(body, budget) {
+            if !current.is_empty() && char_len(&current) + 2 + char_len(&piece) > budget {
+                push_chunk(&mut chunks, &headings, &mut current);
+            }
+            if !current.is_empty() {
+                current.push_str("\n\n");
+            }
+            current.push_str(&piece);
+        }
+    }
+    push_chunk(&mut chunks, &headings, &mut current);
+    chunks
+}
+
+/// Runs of non-blank lines. In Markdown a heading line also starts a new
+/// paragraph.
+fn paragraphs(text: &str, markdown: bool) -> Vec<String> {
+    let mut paragraphs = Vec::new();
+    let mut current: Vec<&str> = Vec::new();
+    for line in text.lines().map(str::trim_end) {
+        let heading = markdown && line.starts_with('#');
+        if (line.trim().is_empty() || heading) && !current.is_empty() {
+            paragraphs.push(current.join("\n"));
+            current.clear();
+        }
+        if !line.trim().is_empty() {
+            current.push(line);
+        }
+    }
+    if !current.is_empty() {
+        paragraphs.push(current.join("\n"));
+    }
+    paragraphs
+}
+
+fn heading_line(headings: &[(usize, String)]) -> Option<String> {
+    (!headings.is_empty()).then(|| {
+        headings
+            .iter()
+            .map(|(_, heading)| heading.as_str())
+            .collect::<Vec<_>>()
+            .join(" > ")
+    })
+}
+
+fn push_chunk(chunks: &mut Vec<String>, headings: &[(usize, String)], current: &mut String) {
+    if current.is_empty() {
+        return;
+    }
+    let body = std::mem::take(current);
+    chunks.push(match heading_line(headings) {
+        Some(heading) => format!("{}\n\n{}", heading, body),
+        None => body,
+    });
+}
+
+/// `paragraph` in pieces of at most `max_chars`, split between words.
+fn split_long(paragraph: &str, max_chars: usize) -> Vec<String> {
+    if char_len(paragraph) <= max_chars {
+        return vec![paragraph.to_string()];
+    }
+
+    let mut pieces = Vec::new();
+    let mut current = String::new();
+    for word in paragraph.split_whitespace() {
+        for part in split_unspaced(word, max_chars) {
+            if !current.is_empty() && char_len(&current) + 1 + char_len(&part) > max_chars {
+                pieces.push(std::mem::take(&mut current));
+            }
+            if !current.is_empty() {
+                current.push(' ');
+            }
+            current.push_str(&part);
+        }
+    }
+    if !current.is_empty() {
+        pieces.push(current);
+    }
+    pieces
+}
+
+/// Splits a run of text without spaces into pieces of at most `max_chars`,
+/// after the last sentence end in each piece when there is one.
+fn split_unspaced(word: &str, max_chars: usize) -> Vec<String> {
+    let mut pieces = Vec::new();
+    let mut rest: Vec<char> = word.chars().collect();
+    while rest.len() > max_chars {
+        let cut = rest[..max_chars]
+            .iter()
+            .rposition(|c| SENTENCE_ENDS.contains(c))
+            .map_or(max_chars, |end| end + 1);
+        pieces.push(rest.drain(..cut).collect());
+    }
+    if !rest.is_empty() {
+        pieces.push(rest.into_iter().collect());
+    }
+    pieces
+}
+
+fn char_len(text: &str) -> usize {
+    text.chars().count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn short_paragraphs_share_a_chunk() {
+        assert_eq!(
+            split("First paragraph.\n\n\nSecond\nparagraph.", false, 100),
+            ["First paragraph.\n\nSecond\nparagraph."]
+        );
+        assert!(split("  \n\n ", false, 100).is_empty());
+    }
+
+    #[test]
+    fn every_markdown_heading_starts_a_chunk_under_its_parents() {
+        let text = "Intro text.\n\
+                    # Pricing\n\
+                    Plans are monthly.\n\
+                    ## Discounts\n\
+                    Schools get 20% off.\n\n\
+                    Ask for a quote.\n\
+                    ### Students\n\
+                    Free.\n\
+                    ## Refunds\n\
+                    Within 30 days.\n\
+                    # Support\n\
+                    Mail us.";
+
+        assert_eq!(
+            split(text, true, 1000),
+            [
+                "Intro text.",
+                "Pricing\n\nPlans are monthly.",
+                "Pricing > Discounts\n\nSchools get 20% off.\n\nAsk for a quote.",
+                "Pricing > Discounts > Students\n\nFree.",
+                "Pricing > Refunds\n\nWithin 30 days.",
+                "Support\n\nMail us.",
+            ]
+        );
+    }
+
+    #[test]
+    fn headings_are_plain_text_outside_markdown() {
+        assert_eq!(
+            split("# Pricing\nPlans are monthly.", false, 1000),
+            ["# Pricing\nPlans are monthly."]
+        );
+    }
+
+    #[test]
+    fn chunks_with_their_heading_stay_within_the_limit() {
+        let text = format!("# FAQ\n{}", "lorem ipsum ".repeat(20));
+        let chunks = split(&text, true, 40);
+
+        assert!(chunks.len() > 1);
+        for chunk in &chunks {
+            assert!(chunk.starts_with("FAQ\n\n"));
+            assert!(char_len(chunk) <= 40, "{:?}", chunk);
+        }
+    }
+
+    #[test]
+    fn text_without_spaces_is_split_after_sentences() {
+        let text = "請求書は毎月末に発行します。お支払いは翌月末までにお願いします。";
+
+        assert_eq!(
+            split(text, false, 20),
+            [
+                "請求書は毎月末に発行します。",
+                "お支払いは翌月末までにお願いします。"
+            ]
+        );
+    }
+}
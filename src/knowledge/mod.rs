@@ -0,0 +1,271 @@
+//! Routes that fill the `EMAIL_DRAFT_CONTEXT` index with knowledge the
+//! drafting prompt can draw on, list what was ingested and remove it again.
+
+pub mod chunk;
+
+use crate::accounts::account_key;
+use crate::authenticate;
+use crate::drive::DriveProvider;
+use crate::error::ApiError;
+use crate::models::{ContextualDocument, IngestRequest, KnowledgeSource};
+use crate::vectorize::{ContextIndex, ContextVector};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use worker::*;
+
+/// Per-account KV key prefix of the ingested sources,
+/// `account:<email>:knowledge:<id>`.
+const SOURCE_KEY_PREFIX: &str = "knowledge:";
+/// Chunk size in characters; a few paragraphs, small enough that several
+/// retrieved chunks fit the drafting prompt.
+const CHUNK_CHARS: usize = 1500;
+/// Each chunk is one embedding call; larger texts should be split up before
+/// ingesting so a request stays inside the Worker time limit.
+const MAX_CHUNKS: usize = 100;
+
+/// Text to ingest, with how to split it and the source name to use when the
+/// request does not give one.
+struct Content {
+    text: String,
+    markdown: bool,
+    default_source: String,
+}
+
+/// The mailbox a request is about: `?account=`, defaulting to `USER_EMAIL`.
+fn requested_account(req: &Request, env: &Env) -> Result<Option<String>> {
+    Ok(req
+        .url()?
+        .query_pairs()
+        .find(|(key, _)| key == "account")
+        .map(|(_, value)| value.into_owned())
+        .or_else(|| env.secret("USER_EMAIL").ok().map(|s| s.to_string())))
+}
+
+fn source_key(account: &str, id: &str) -> String {
+    account_key(account, &format!("{}{}", SOURCE_KEY_PREFIX, id))
+}
+
+/// Chunks, embeds and stores the text, Markdown or Drive file in the request
+/// body for the mailbox given in `?account=` (see `requested_account`).
+/// Embeddings and Drive access use that mailbox's settings and credentials,
+/// and the vectors go into its namespace of the index.
+pub async fn handle_ingest(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    if !crate::is_authorized(&req, &ctx.env) {
+        return Response::error("Unauthorized", 401);
+    }
+
+    let account = match requested_account(&req, &ctx.env)? {
+        Some(account) => account,
+        None => return Response::error("Missing `account` parameter.", 400),
+    };
+
+    let request = match req.json::<IngestRequest>().await {
+        Ok(request) => request,
+        Err(e) => return Response::error(format!("Invalid ingestion request: {}", e), 400),
+    };
+
+    let mut logs: Vec<String> = Vec::new();
+    let pipeline = authenticate(&ctx.env, &account, &mut logs).await?;
+    let index = match &pipeline.context_index {
+        Some(index) => index,
+        None => return Response::error("The context index binding is not configured.", 500),
+    };
+
+    let content = match (request.text, request.markdown, request.drive_file_id) {
+        (Some(text), None, None) => Content {
+            text,
+            markdown: false,
+            default_source: "text".to_string(),
+        },
+        (None, Some(text), None) => Content {
+            text,
+            markdown: true,
+            default_source: "markdown".to_string(),
+        },
+        (None, None, Some(file_id)) => match drive_content(pipeline.drive.as_ref(), &file_id).await
+        {
+            Ok(Some(content)) => content,
+            Ok(None) => {
+                return Response::error(
+                    format!("Drive file {} is not a document or text file.", file_id),
+                    415,
+                )
+            }
+            Err(e) => {
+                return Response::error(
+                    format!("Could not read Drive file {}: {}", file_id, e),
+                    e.status.filter(|s| *s < 500).unwrap_or(502),
+                )
+            }
+        },
+        _ => {
+            return Response::error(
+                "Provide exactly one of `text`, `markdown` or `drive_file_id`.",
+                400,
+            )
+        }
+    };
+
+    let chunks = chunk::split(&content.text, content.markdown, CHUNK_CHARS);
+    if chunks.is_empty() {
+        return Response::error("Nothing to ingest: the content is empty.", 400);
+    }
+    if chunks.len() > MAX_CHUNKS {
+        return Response::error(
+            format!(
+                "Content splits into {} chunks; ingest at most {} at a time.",
+                chunks.len(),
+                MAX_CHUNKS
+            ),
+            413,
+        );
+    }
+
+    let source = KnowledgeSource {
+        id: random_id()?,
+        account: pipeline.user_email.clone(),
+        source: request.source.unwrap_or(content.default_source),
+        tags: request.tags,
+        created_at: chrono::Utc::now().timestamp(),
+        chunks: chunks.len(),
+    };
+
+    let mut vectors = Vec::with_capacity(chunks.len());
+    for (id, text) in source.vector_ids().into_iter().zip(chunks) {
+        let values = match pipeline.llm.embed(&text).await {
+            Ok(values) => values,
+            Err(e) => return Response::error(format!("Failed to embed chunk {}: {}", id, e), 502),
+        };
+        vectors.push(ContextVector {
+            id,
+            values,
+            namespace: pipeline.context_namespace.clone(),
+            metadata: ContextualDocument {
+                text,
+                created_at: source.created_at,
+                source: source.source.clone(),
+                tags: source.tags.clone(),
+            },
+        });
+    }
+    index.upsert(&vectors).await?;
+
+    let kv = ctx.env.kv("GMAIL_AUTH")?;
+    kv.put(&source_key(&source.account, &source.id), &source)?
+        .execute()
+        .await?;
+
+    Ok(Response::from_json(&source)?.with_status(201))
+}
+
+/// Lists the sources ingested for `?account=`, newest first; `?tag=` keeps
+/// only those with that tag.
+pub async fn handle_list(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    if !crate::is_authorized(&req, &ctx.env) {
+        return Response::error("Unauthorized", 401);
+    }
+
+    let account = match requested_account(&req, &ctx.env)? {
+        Some(account) => account,
+        None => return Response::error("Missing `account` parameter.", 400),
+    };
+    let tag = req
+        .url()?
+        .query_pairs()
+        .find(|(key, _)| key == "tag")
+        .map(|(_, value)| value.into_owned());
+
+    let kv = ctx.env.kv("GMAIL_AUTH")?;
+    let mut sources = Vec::new();
+    let mut cursor = None;
+    loop {
+        let mut list = kv.list().prefix(account_key(&account, SOURCE_KEY_PREFIX));
+        if let Some(cursor) = cursor {
+            list = list.cursor(cursor);
+        }
+        let page = list.execute().await?;
+
+        for key in page.keys {
+            if let Some(source) = kv.get(&key.name).json::<KnowledgeSource>().await? {
+                sources.push(source);
+            }
+        }
+        if page.list_complete || page.cursor.is_none() {
+            break;
+        }
+        cursor = page.cursor;
+    }
+
+    sources.retain(|source| tag.as_ref().is_none_or(|tag| source.tags.contains(tag)));
+    sources.sort_by_key(|source| std::cmp::Reverse(source.created_at));
+    Response::from_json(&sources)
+}
+
+/// Removes a source ingested for `?account=` and all of its chunks from the
+/// index. Sources of other accounts are not found.
+pub async fn handle_delete(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    if !crate::is_authorized(&req, &ctx.env) {
+        return Response::error("Unauthorized", 401);
+    }
+
+    let account = match requested_account(&req, &ctx.env)? {
+        Some(account) => account,
+        None => return Response::error("Missing `account` parameter.", 400),
+    };
+    let id = match ctx.param("id") {
+        Some(id) => id.clone(),
+        None => return Response::error("Missing source ID.", 400),
+    };
+
+    let kv = ctx.env.kv("GMAIL_AUTH")?;
+    let key = source_key(&account, &id);
+    let source = match kv.get(&key).json::<KnowledgeSource>().await? {
+        Some(source) => source,
+        None => return Response::error(format!("No knowledge source {}.", id), 404),
+    };
+
+    ContextIndex::from_env(&ctx.env)?
+        .delete(&source.vector_ids())
+        .await?;
+    kv.delete(&key).await?;
+
+    Response::ok(format!(
+        "Deleted '{}' ({} chunks).",
+        source.source, source.chunks
+    ))
+}
+
+/// The text of a Drive file: Google Docs and Slides exported as plain text,
+/// Sheets as CSV (first sheet only), and text and Markdown files as they
+/// are. `None` for any other type.
+async fn drive_content(
+    drive: &dyn DriveProvider,
+    file_id: &str,
+) -> std::result::Result<Option<Content>, ApiError> {
+    let file = drive.get_file(file_id).await?;
+    let markdown = file.mime_type == "text/markdown" || file.name.ends_with(".md");
+
+    let data = match file.mime_type.as_str() {
+        "application/vnd.google-apps.document" | "application/vnd.google-apps.presentation" => {
+            drive.export_file(file_id, "text/plain").await?
+        }
+        "application/vnd.google-apps.spreadsheet" => drive.export_file(file_id, "text/csv").await?,
+        mime_type if mime_type.starts_with("text/") || markdown => {
+            drive.download_file(file_id).await?
+        }
+        _ => return Ok(None),
+    };
+
+    Ok(Some(Content {
+        text: String::from_utf8_lossy(&data).into_owned(),
+        markdown,
+        default_source: file.name,
+    }))
+}
+
+/// A short random ID for a new source.
+fn random_id() -> Result<String> {
+    let mut bytes = [0u8; 12];
+    getrandom::getrandom(&mut bytes)
+        .map_err(|e| Error::from(format!("Failed to generate random bytes: {}", e)))?;
+    Ok(URL_SAFE_NO_PAD.encode(bytes))
+}
@@ -0,0 +1,66 @@
+use super::{entry, Ledger};
+use crate::models::{Classification, LedgerAction, LedgerEntry};
+use async_trait::async_trait;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+use worker::Result;
+
+/// In-memory ledger for exercising the pipeline without KV. Clones share
+/// the entries.
+#[derive(Default, Clone)]
+pub struct FakeLedger {
+    entries: Rc<RefCell<HashMap<String, LedgerEntry>>>,
+    /// Failed attempts by message ID and whether they were retryable.
+    attempts: Rc<RefCell<HashMap<(String, bool), u32>>>,
+    claims: Rc<RefCell<HashSet<String>>>,
+}
+
+impl FakeLedger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, message_id: &str) -> Option<LedgerEntry> {
+        self.entries.borrow().get(message_id).cloned()
+    }
+}
+
+#[async_trait(?Send)]
+impl Ledger for FakeLedger {
+    async fn lookup(&self, message_id: &str) -> Result<Option<LedgerEntry>> {
+        Ok(self.get(message_id))
+    }
+
+    async fn record(
+        &self,
+        message_id: &str,
+        classification: Option<&Classification>,
+        action: LedgerAction,
+        draft_id: Option<String>,
+    ) -> Result<LedgerEntry> {
+        let entry = entry(message_id, classification, action, draft_id);
+        self.entries
+            .borrow_mut()
+            .insert(message_id.to_string(), entry.clone());
+        Ok(entry)
+    }
+
+    async fn record_failure(&self, message_id: &str, retryable: bool) -> Result<u32> {
+        let mut attempts = self.attempts.borrow_mut();
+        let count = attempts
+            .entry((message_id.to_string(), retryable))
+            .or_insert(0);
+        *count += 1;
+        Ok(*count)
+    }
+
+    async fn claim(&self, message_id: &str) -> Result<bool> {
+        Ok(self.claims.borrow_mut().insert(message_id.to_string()))
+    }
+
+    async fn release(&self, message_id: &str) -> Result<()> {
+        self.claims.borrow_mut().remove(message_id);
+        Ok(())
+    }
+}
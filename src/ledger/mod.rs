@@ -0,0 +1,206 @@
+#[cfg(test)]
+pub mod fake;
+
+use crate::accounts::account_key;
+use crate::models::{Classification, LedgerAction, LedgerClassification, LedgerEntry};
+use async_trait::async_trait;
+use worker::*;
+
+/// Per-account KV key prefix; the Gmail message ID is appended.
+const LEDGER_KEY_PREFIX: &str = "processed";
+/// Per-account KV key prefix of the failed-attempt counts.
+const ATTEMPTS_KEY_PREFIX: &str = "attempts";
+/// Per-account KV key prefix of the temporary-failure counts.
+const RETRIES_KEY_PREFIX: &str = "retries";
+/// Per-account KV key prefix of the claims taken without D1.
+const CLAIM_KEY_PREFIX: &str = "claim";
+/// D1 database holding the message claims (see `migrations/`).
+pub const CLAIMS_BINDING: &str = "LEDGER_DB";
+/// How long a claim keeps other runs away from a message. Longer than a
+/// run can take, so only a run that died while holding it lets it lapse.
+const CLAIM_TTL_SECONDS: u64 = 15 * 60;
+
+/// What the pipeline already did with each message of one account.
+/// `KvLedger` keeps it in KV; `fake::FakeLedger` keeps it in memory.
+#[async_trait(?Send)]
+pub trait Ledger {
+    /// Returns what the pipeline already did with `message_id`, if anything.
+    async fn lookup(&self, message_id: &str) -> Result<Option<LedgerEntry>>;
+
+    /// Records the outcome for `message_id`.
+    async fn record(
+        &self,
+        message_id: &str,
+        classification: Option<&Classification>,
+        action: LedgerAction,
+        draft_id: Option<String>,
+    ) -> Result<LedgerEntry>;
+
+    /// Counts a failed attempt at processing `message_id` and returns the
+    /// number of failed attempts so far, this one included. `retryable`
+    /// failures are counted apart from the others.
+    async fn record_failure(&self, message_id: &str, retryable: bool) -> Result<u32>;
+
+    /// Takes `message_id` for this run. Returns `false` when another run (the
+    /// push webhook or the cron) holds it, in which case this run must leave
+    /// the message alone.
+    async fn claim(&self, message_id: &str) -> Result<bool>;
+
+    /// Gives up the claim so a later run can retry the message right away.
+    async fn release(&self, message_id: &str) -> Result<()>;
+}
+
+/// `Ledger` stored in KV under `account:<email>:processed:<message id>`.
+/// Claims are taken in D1, whose upsert makes check-and-set atomic; without
+/// the `claims` database they fall back to KV, where two runs starting on
+/// the same message within a moment of each other can both get it.
+pub struct KvLedger {
+    kv: kv::KvStore,
+    claims: Option<D1Database>,
+    user_email: String,
+}
+
+impl KvLedger {
+    pub fn new(kv: kv::KvStore, claims: Option<D1Database>, user_email: String) -> Self {
+        Self {
+            kv,
+            claims,
+            user_email,
+        }
+    }
+
+    fn key(&self, prefix: &str, message_id: &str) -> String {
+        account_key(&self.user_email, &format!("{}:{}", prefix, message_id))
+    }
+}
+
+/// Builds the entry `record` stores, stamped with the current time.
+pub fn entry(
+    message_id: &str,
+    classification: Option<&Classification>,
+    action: LedgerAction,
+    draft_id: Option<String>,
+) -> LedgerEntry {
+    LedgerEntry {
+        message_id: message_id.to_string(),
+        classification: classification.map(LedgerClassification::from),
+        action,
+        draft_id,
+        processed_at: chrono::Utc::now().to_rfc3339(),
+    }
+}
+
+#[async_trait(?Send)]
+impl Ledger for KvLedger {
+    async fn lookup(&self, message_id: &str) -> Result<Option<LedgerEntry>> {
+        Ok(self
+            .kv
+            .get(&self.key(LEDGER_KEY_PREFIX, message_id))
+            .json::<LedgerEntry>()
+            .await?)
+    }
+
+    /// Entries never expire: an unread message can stay in the listing
+    /// indefinitely and must not be drafted twice.
+    async fn record(
+        &self,
+        message_id: &str,
+        classification: Option<&Classification>,
+        action: LedgerAction,
+        draft_id: Option<String>,
+    ) -> Result<LedgerEntry> {
+        let entry = entry(message_id, classification, action, draft_id);
+        self.kv
+            .put(&self.key(LEDGER_KEY_PREFIX, message_id), &entry)?
+            .execute()
+            .await?;
+        Ok(entry)
+    }
+
+    /// KV has no atomic increment; two runs failing on the same message at
+    /// once may count one attempt, which only delays giving up.
+    async fn record_failure(&self, message_id: &str, retryable: bool) -> Result<u32> {
+        let prefix = if retryable {
+            RETRIES_KEY_PREFIX
+        } else {
+            ATTEMPTS_KEY_PREFIX
+        };
+        let key = self.key(prefix, message_id);
+        let attempts = self
+            .kv
+            .get(&key)
+            .text()
+            .await?
+            .and_then(|value| value.parse::<u32>().ok())
+            .unwrap_or(0)
+            + 1;
+        self.kv.put(&key, attempts.to_string())?.execute().await?;
+        Ok(attempts)
+    }
+
+    async fn claim(&self, message_id: &str) -> Result<bool> {
+        let now = chrono::Utc::now().timestamp();
+        let Some(db) = &self.claims else {
+            let key = self.key(CLAIM_KEY_PREFIX, message_id);
+            if self.kv.get(&key).text().await?.is_some() {
+                return Ok(false);
+            }
+            self.kv
+                .put(&key, now.to_string())?
+                .expiration_ttl(CLAIM_TTL_SECONDS)
+                .execute()
+                .await?;
+            return Ok(true);
+        };
+
+        let result = db
+            .prepare(
+                "INSERT INTO message_claims (account, message_id, claimed_at) VALUES (?1, ?2, ?3) \
+                 ON CONFLICT (account, message_id) DO UPDATE SET claimed_at = excluded.claimed_at \
+                 WHERE message_claims.claimed_at <= ?4",
+            )
+            .bind(&[
+                self.user_email.as_str().into(),
+                message_id.into(),
+                (now as f64).into(),
+                ((now - CLAIM_TTL_SECONDS as i64) as f64).into(),
+            ])?
+            .run()
+            .await?;
+        Ok(result
+            .meta()?
+            .and_then(|meta| meta.changes)
+            .is_some_and(|changes| changes > 0))
+    }
+
+    async fn release(&self, message_id: &str) -> Result<()> {
+        let Some(db) = &self.claims else {
+            return self
+                .kv
+                .delete(&self.key(CLAIM_KEY_PREFIX, message_id))
+                .await
+                .map_err(Error::from);
+        };
+
+        db.prepare("DELETE FROM message_claims WHERE account = ?1 AND message_id = ?2")
+            .bind(&[self.user_email.as_str().into(), message_id.into()])?
+            .run()
+            .await?;
+        Ok(())
+    }
+}
+
+/// Deletes the expired claims of all accounts. Claims of handled messages are
+/// kept until they expire, as KV may not show their ledger entry everywhere
+/// yet. Does nothing without the claims database.
+pub async fn prune_claims(env: &Env) -> Result<()> {
+    let Ok(db) = env.d1(CLAIMS_BINDING) else {
+        return Ok(());
+    };
+    let cutoff = chrono::Utc::now().timestamp() - CLAIM_TTL_SECONDS as i64;
+    db.prepare("DELETE FROM message_claims WHERE claimed_at <= ?1")
+        .bind(&[(cutoff as f64).into()])?
+        .run()
+        .await?;
+    Ok(())
+}
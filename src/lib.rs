@@ -1,39 +1,366 @@
-use base64::{engine::general_purpose::URL_SAFE, Engine as _};
+use std::rc::Rc;
 use worker::*;
 
-mod models;
+pub mod models;
 
-mod drive;
-mod gemini;
-mod gmail;
+pub mod accounts;
+pub mod drive;
+pub mod error;
+pub mod gemini;
+pub mod gmail;
+pub mod knowledge;
+pub mod ledger;
+pub mod llm;
+pub mod mail;
+pub mod oauth;
+pub mod push;
+pub mod vectorize;
+
+/// How long scheduled run reports are kept in KV before they expire.
+const RUN_REPORT_TTL_SECONDS: u64 = 7 * 24 * 60 * 60;
+/// Used when the `MAX_MESSAGES_PER_RUN` var is unset; keeps a run well inside
+/// the Worker time limit.
+const DEFAULT_MAX_MESSAGES_PER_RUN: usize = 20;
+/// Per-account KV key of the cached send-as aliases and their signatures.
+const SEND_AS_CACHE_KEY: &str = "send_as";
+/// Aliases and signatures rarely change; an hour keeps edits visible soon
+/// without listing them on every run.
+const SEND_AS_CACHE_TTL_SECONDS: u64 = 60 * 60;
+/// Estimated tokens of earlier thread messages given to the drafting prompt.
+const THREAD_TOKEN_BUDGET: usize = 3000;
+/// Context documents retrieved per draft.
+const CONTEXT_TOP_K: u32 = 5;
+/// Cosine similarity below which a retrieved document is considered
+/// unrelated and left out.
+const CONTEXT_MIN_SCORE: f64 = 0.6;
+/// Failed attempts at processing a message before it is given up on.
+const MAX_ATTEMPTS: u32 = 3;
+/// Temporary failures (see `mark_failed`) before a message is given up on:
+/// about a day of cron runs, so only a long outage or a message that keeps
+/// timing out ends up `Bot/Failed`.
+const MAX_TEMPORARY_FAILURES: u32 = 96;
+/// Characters of the message embedded for retrieval; keeps the query within
+/// the embedding models' input limits.
+const CONTEXT_QUERY_CHARS: usize = 4000;
 
 #[event(fetch)]
-pub async fn main(_req: Request, env: Env, _ctx: Context) -> Result<Response> {
-    let mut logs: Vec<String> = Vec::new();
-    logs.push("Fetch event started!".to_string());
+pub async fn main(req: Request, env: Env, _ctx: Context) -> Result<Response> {
+    Router::new()
+        .get_async("/", |req, ctx| async move {
+            if !is_authorized(&req, &ctx.env) {
+                return Response::error("Unauthorized", 401);
+            }
+            match run_pipeline(&ctx.env).await {
+                Ok((logs, _)) => Response::ok(logs.join("\n")),
+                Err(e) => Response::error(e.to_string(), 500),
+            }
+        })
+        .get_async("/oauth/start", oauth::handle_start)
+        .get_async("/oauth/callback", oauth::handle_callback)
+        .post_async("/gmail/push", push::handle_notification)
+        .post_async("/gmail/watch", push::handle_watch_renewal)
+        .post_async("/knowledge", knowledge::handle_ingest)
+        .get_async("/knowledge", knowledge::handle_list)
+        .delete_async("/knowledge/:id", knowledge::handle_delete)
+        .run(req, env)
+        .await
+}
+
+#[event(scheduled)]
+pub async fn scheduled(event: ScheduledEvent, env: Env, _ctx: ScheduleContext) {
+    let started_at = chrono::Utc::now();
+    let mut result = run_pipeline(&env).await;
+    if let Ok((logs, _)) = result.as_mut() {
+        if let Err(e) = push::renew_watches_if_expiring(&env, logs).await {
+            logs.push(format!("Failed to renew Gmail watches: {}", e));
+        }
+        if let Err(e) = ledger::prune_claims(&env).await {
+            logs.push(format!("Failed to prune message claims: {}", e));
+        }
+    }
+
+    let report = models::RunReport {
+        trigger: event.cron(),
+        started_at: started_at.to_rfc3339(),
+        finished_at: chrono::Utc::now().to_rfc3339(),
+        success: result.is_ok(),
+        error: result.as_ref().err().map(|e| e.to_string()),
+        stats: result.map(|(_, stats)| stats).unwrap_or_default(),
+    };
+
+    if let Err(e) = save_run_report(&env, &report).await {
+        console_error!("Failed to save run report: {}", e);
+    }
+}
+
+/// Checks the shared `WORKER_AUTH_TOKEN` secret, sent either as a bearer token
+/// or as a `token` query parameter (Pub/Sub push subscriptions cannot set
+/// headers, so their endpoint URL carries it).
+pub fn is_authorized(req: &Request, env: &Env) -> bool {
+    let expected = match env.secret("WORKER_AUTH_TOKEN") {
+        Ok(secret) => secret.to_string(),
+        Err(_) => return false,
+    };
+
+    let bearer = req
+        .headers()
+        .get("Authorization")
+        .ok()
+        .flatten()
+        .and_then(|value| value.strip_prefix("Bearer ").map(str::to_string));
+    let query = req.url().ok().and_then(|url| {
+        url.query_pairs()
+            .find(|(key, _)| key == "token")
+            .map(|(_, value)| value.into_owned())
+    });
+
+    bearer
+        .into_iter()
+        .chain(query)
+        .any(|provided| constant_time_eq(provided.as_bytes(), expected.as_bytes()))
+}
 
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Stores the report under `run_report:latest` and a timestamped key so
+/// past scheduled runs can be inspected with `wrangler kv key get`. Both
+/// expire after `RUN_REPORT_TTL_SECONDS`.
+async fn save_run_report(env: &Env, report: &models::RunReport) -> Result<()> {
+    let kv = env.kv("GMAIL_AUTH")?;
+    let report_json = serde_json::to_string(report)?;
+
+    kv.put("run_report:latest", &report_json)?
+        .expiration_ttl(RUN_REPORT_TTL_SECONDS)
+        .execute()
+        .await?;
+    kv.put(&format!("run_report:{}", report.started_at), report_json)?
+        .expiration_ttl(RUN_REPORT_TTL_SECONDS)
+        .execute()
+        .await?;
+
+    Ok(())
+}
+
+/// Providers, identity and settings of the mailbox a pipeline run works on.
+pub struct PipelineContext {
+    pub mail: Box<dyn mail::MailProvider>,
+    pub drive: Box<dyn drive::DriveProvider>,
+    pub ledger: Box<dyn ledger::Ledger>,
+    pub user_email: String,
+    /// `user_email` and its send-as aliases; never included in replies.
+    pub own_addresses: Vec<String>,
+    /// The account's send-as aliases, which carry its Gmail signatures.
+    pub send_as: Vec<models::SendAs>,
+    pub settings: models::AccountSettings,
+    pub llm: Box<dyn llm::LlmClient>,
+    /// Knowledge snippets to draw on; `None` when the binding is missing.
+    pub context_index: Option<vectorize::ContextIndex>,
+    /// This account's part of the context index (see `vectorize::namespace`).
+    pub context_namespace: String,
+    /// Upper bound on the messages a single run processes for this account.
+    pub max_messages_per_run: usize,
+}
+
+impl PipelineContext {
+    /// Assembles a context from its parts, without a context index and with
+    /// the default `max_messages_per_run`. Names missing from `settings` are
+    /// taken from the primary send-as alias.
+    pub fn new(
+        user_email: &str,
+        mail: Box<dyn mail::MailProvider>,
+        drive: Box<dyn drive::DriveProvider>,
+        ledger: Box<dyn ledger::Ledger>,
+        llm: Box<dyn llm::LlmClient>,
+        mut settings: models::AccountSettings,
+        send_as: Vec<models::SendAs>,
+    ) -> Self {
+        let display_name = send_as
+            .iter()
+            .find(|alias| alias.is_primary)
+            .and_then(|alias| alias.display_name.as_deref());
+        settings.fill_names(display_name, user_email);
+        let mut own_addresses = vec![user_email.to_string()];
+        own_addresses.extend(send_as.iter().map(|s| s.send_as_email.clone()));
+        let context_namespace = vectorize::namespace(user_email, llm.embedding_model());
+
+        Self {
+            mail,
+            drive,
+            ledger,
+            user_email: user_email.to_string(),
+            own_addresses,
+            send_as,
+            settings,
+            llm,
+            context_index: None,
+            context_namespace,
+            max_messages_per_run: DEFAULT_MAX_MESSAGES_PER_RUN,
+        }
+    }
+}
+
+/// Reads the secrets and the account's state from KV, makes sure its refresh
+/// token yields an access token and returns the context every pipeline
+/// entrypoint needs.
+pub async fn authenticate(
+    env: &Env,
+    user_email: &str,
+    logs: &mut Vec<String>,
+) -> Result<PipelineContext> {
     let client_id = env.secret("GMAIL_CLIENT_ID")?.to_string();
     let client_secret = env.secret("GMAIL_CLIENT_SECRET")?.to_string();
-    let user_email = env.secret("USER_EMAIL")?.to_string();
     let kv = env.kv("GMAIL_AUTH")?;
-    let gemini_api_key = env.secret("GEMINI_API_KEY")?.to_string();
 
-    let refresh_token = match kv.get("refresh_token").text().await? {
+    let refresh_token = match accounts::load_refresh_token(env, &kv, user_email).await? {
         Some(token) => token,
-        None => return Response::error("FATAL: Refresh token not found.", 500),
+        None => {
+            return Err(Error::from(format!(
+                "FATAL: Refresh token not found for {}. Visit /oauth/start?account={}&token=<WORKER_AUTH_TOKEN> to connect the mailbox.",
+                user_email, user_email
+            )))
+        }
+    };
+    let settings = accounts::load_settings(env, &kv, user_email).await?;
+    let llm = match &settings.llm {
+        Some(llm_settings) => llm::from_settings(env, llm_settings)?,
+        None => llm::from_settings(env, &llm::settings_from_env(env)?)?,
     };
 
-    let access_token =
-        match gmail::client::get_access_token(&client_id, &client_secret, &refresh_token).await {
-            Ok(token_response) => {
-                logs.push("Successfully authenticated with Google.".to_string());
-                token_response.access_token
-            }
-            Err(e) => return Response::error(format!("Failed to get access token: {}", e), 500),
-        };
+    let tokens = Rc::new(oauth::token::TokenManager::new(
+        client_id,
+        client_secret,
+        refresh_token,
+        kv.clone(),
+        accounts::account_key(user_email, "access_token"),
+    ));
+    match tokens.access_token().await {
+        Ok(_) => logs.push("Successfully authenticated with Google.".to_string()),
+        Err(e) if e.is_auth_expired() => {
+            return Err(Error::from(format!(
+                "Refresh token for {} was revoked or expired ({}). Visit /oauth/start?account={}&token=<WORKER_AUTH_TOKEN> to reconnect the mailbox.",
+                user_email, e, user_email
+            )))
+        }
+        Err(e) => return Err(Error::from(format!("Failed to get access token: {}", e))),
+    }
+
+    let mail: Box<dyn mail::MailProvider> = Box::new(gmail::provider::GmailProvider::new(
+        tokens.clone(),
+        user_email.to_string(),
+    ));
+    let send_as = load_send_as(&kv, user_email, mail.as_ref(), logs).await;
+    let claims = match env.d1(ledger::CLAIMS_BINDING) {
+        Ok(db) => Some(db),
+        Err(e) => {
+            logs.push(format!(
+                "Claims database unavailable, claiming messages in KV: {}",
+                e
+            ));
+            None
+        }
+    };
+    let mut ctx = PipelineContext::new(
+        user_email,
+        mail,
+        Box::new(drive::provider::GoogleDriveProvider::new(tokens)),
+        Box::new(ledger::KvLedger::new(kv, claims, user_email.to_string())),
+        llm,
+        settings,
+        send_as,
+    );
+
+    if let Some(max) = env
+        .var("MAX_MESSAGES_PER_RUN")
+        .ok()
+        .and_then(|value| value.to_string().parse::<usize>().ok())
+    {
+        ctx.max_messages_per_run = max;
+    }
+    ctx.context_index = match vectorize::ContextIndex::from_env(env) {
+        Ok(index) => Some(index),
+        Err(e) => {
+            logs.push(format!(
+                "Context index unavailable, drafting without retrieved context: {}",
+                e
+            ));
+            None
+        }
+    };
+
+    Ok(ctx)
+}
+
+/// The account's send-as aliases, from the KV cache when it is fresh. Without
+/// them only `user_email` is excluded from replies and drafts fall back to
+/// the configured signature.
+async fn load_send_as(
+    kv: &kv::KvStore,
+    user_email: &str,
+    mail: &dyn mail::MailProvider,
+    logs: &mut Vec<String>,
+) -> Vec<models::SendAs> {
+    let key = accounts::account_key(user_email, SEND_AS_CACHE_KEY);
+    if let Ok(Some(send_as)) = kv.get(&key).json::<Vec<models::SendAs>>().await {
+        return send_as;
+    }
+
+    let send_as = match mail.list_send_as().await {
+        Ok(send_as) => send_as,
+        Err(e) => {
+            logs.push(format!(
+                "Could not list send-as aliases, only excluding {} from replies: {}",
+                user_email, e
+            ));
+            return Vec::new();
+        }
+    };
+    let cached = match kv.put(&key, &send_as) {
+        Ok(put) => {
+            put.expiration_ttl(SEND_AS_CACHE_TTL_SECONDS)
+                .execute()
+                .await
+        }
+        Err(e) => Err(e),
+    };
+    if let Err(e) = cached {
+        logs.push(format!("Could not cache send-as aliases: {}", e));
+    }
+    send_as
+}
+
+/// Runs one full triage-and-draft pass over the unread mail of every
+/// registered account and returns the log lines describing what was done,
+/// with the outcome counts. A failing account is logged and does not stop
+/// the others.
+pub async fn run_pipeline(env: &Env) -> Result<(Vec<String>, models::RunStats)> {
+    let mut logs: Vec<String> = Vec::new();
+    let mut stats = models::RunStats::default();
+    logs.push("Pipeline run started!".to_string());
+
+    for user_email in accounts::list_accounts(env).await? {
+        logs.push(format!("\n##### Account: {} #####", user_email));
+        stats.accounts += 1;
+        if let Err(e) = run_account_pipeline(env, &user_email, &mut stats, &mut logs).await {
+            logs.push(format!("Account {} failed: {}", user_email, e));
+            stats.failed_accounts += 1;
+        }
+    }
+
+    Ok((logs, stats))
+}
+
+async fn run_account_pipeline(
+    env: &Env,
+    user_email: &str,
+    stats: &mut models::RunStats,
+    logs: &mut Vec<String>,
+) -> Result<()> {
+    let ctx = authenticate(env, user_email, logs).await?;
 
     logs.push("Checking for unread emails...".to_string());
-    match gmail::client::find_unread_emails(&access_token, &user_email).await {
+    match list_unread_batch(&ctx).await {
         Ok(messages) => {
             if messages.is_empty() {
                 logs.push("No unread emails found.".to_string());
@@ -43,347 +370,1201 @@ pub async fn main(_req: Request, env: Env, _ctx: Context) -> Result<Response> {
                     messages.len()
                 ));
                 for (i, message_id) in messages.iter().enumerate() {
-                    match gmail::client::get_email_details(
-                        &access_token,
-                        &user_email,
-                        &message_id.id,
-                    )
-                    .await
-                    {
-                        Ok(details) => {
-                            let from = details
-                                .payload
-                                .headers
-                                .iter()
-                                .find(|h| h.name == "From")
-                                .map_or("Unknown Sender", |h| &h.value);
-                            let to = details
-                                .payload
-                                .headers
-                                .iter()
-                                .find(|h| h.name == "To")
-                                .map_or("", |h| h.value.as_str());
-                            let cc = details
-                                .payload
-                                .headers
-                                .iter()
-                                .find(|h| h.name == "Cc")
-                                .map_or("", |h| h.value.as_str());
-                            let subject = details
-                                .payload
-                                .headers
-                                .iter()
-                                .find(|h| h.name == "Subject")
-                                .map_or("No Subject", |h| &h.value);
-                            let body = find_plain_text_body(&details.payload)
-                                .map(|data| {
-                                    URL_SAFE
-                                        .decode(data)
-                                        .ok()
-                                        .map(|decoded| {
-                                            String::from_utf8_lossy(&decoded).to_string()
-                                        })
-                                        .unwrap_or_else(|| "Could not decode body.".to_string())
-                                })
-                                .unwrap_or_else(|| "No plain text body found".to_string());
-
-                            let classification_prompt =
-                                gemini::prompts::get_classification_prompt(from, subject, &body);
-
-                            let gemini_decision = match gemini::client::call_gemini(
-                                &gemini_api_key,
-                                &classification_prompt,
-                            )
-                            .await
-                            {
-                                Ok(text) => text.trim().to_uppercase(),
-                                Err(e) => format!("Gemini Error: {}", e),
-                            };
-
-                            logs.push(format!("\n===== Email #{} =====", i + 1));
-                            logs.push(format!("- Subject: {}", subject));
-                            logs.push(format!("- Needs Reply?: {}", gemini_decision));
-
-                            if gemini_decision == "YES" {
-                                logs.push("- Decision is YES. Drafting reply...".to_string());
-
-                                let mut to_recipients: Vec<&str> =
-                                    from.split(',').chain(to.split(',')).collect();
-                                let mut cc_recipients: Vec<&str> = cc.split(',').collect();
-
-                                to_recipients.retain(|email| {
-                                    !email.contains(&user_email) && !email.trim().is_empty()
-                                });
-                                cc_recipients.retain(|email| {
-                                    !email.contains(&user_email) && !email.trim().is_empty()
-                                });
-
-                                to_recipients.sort();
-                                cc_recipients.dedup();
-                                let to_all = to_recipients.join(", ");
-
-                                cc_recipients.sort();
-                                cc_recipients.dedup();
-                                let cc_all = cc_recipients.join(", ");
-
-                                let draft_prompt = gemini::prompts::get_drafting_prompt(
-                                    from, subject, &body, None,
-                                );
-
-                                match gemini::client::call_gemini(&gemini_api_key, &draft_prompt)
-                                    .await
-                                {
-                                    Ok(draft_text) => {
-                                        logs.push(format!("- Draft from Gemini: {}", draft_text));
-                                        match gmail::client::create_draft_with_attachment(
-                                            &access_token,
-                                            &user_email,
-                                            &message_id.thread_id,
-                                            &to_all,
-                                            &cc_all,
-                                            subject,
-                                            &draft_text,
-                                            None,
-                                        )
-                                        .await
-                                        {
-                                            Ok(_) => {
-                                                logs.push(
-                                                    "- Successfully created draft in Gmail."
-                                                        .to_string(),
-                                                );
-                                                match gmail::client::mark_as_read(&access_token, &user_email, &message_id.id).await {
-                                                    Ok(_) => logs.push("- Successfully marked original email as read.".to_string()),
-                                                    Err(e) => logs.push(format!("- Failed to mark email as read: {}", e)),
-                                                }
-                                            }
-                                            Err(e) => logs
-                                                .push(format!("- Failed to create draft: {}", e)),
-                                        }
-                                    }
-                                    Err(e) => logs.push(format!(
-                                        "- Failed to generate draft from Gemini: {}",
-                                        e
-                                    )),
-                                }
-                            } else if gemini_decision == "IS_FILE_REQUEST" {
-                                logs.push(
-                                    "- ✅ INTENT: File Request Detected. Proceeding to file research..."
-                                        .to_string(),
-                                );
-
-                                let keywords_prompt =
-                                    gemini::prompts::get_search_keywords_prompt(&body);
-
-                                match gemini::client::call_gemini(&gemini_api_key, &keywords_prompt)
-                                    .await
-                                {
-                                    Ok(search_keywords) => {
-                                        // 2. Build a robust search query for the Drive API.
-                                        // This splits keywords by comma and uses 'or' to find any match.
-                                        let query = search_keywords
-                                            .trim_matches('\'')
-                                            .split(',')
-                                            .map(|s| s.trim())
-                                            .filter(|s| !s.is_empty())
-                                            .map(|word| format!("name contains '{}'", word))
-                                            .collect::<Vec<_>>()
-                                            .join(" or ");
-
-                                        logs.push(format!(
-                                            "- Executing Drive search with query: {}",
-                                            &query
-                                        ));
-
-                                        // 3. Call our new drive client to search for files
-                                        match drive::client::search_files(&access_token, &query)
-                                            .await
-                                        {
-                                            Ok(files) => {
-                                                if files.is_empty() {
-                                                    logs.push("- ⚠️ No files found matching the search query.".to_string());
-                                                    // FUTURE: Phase 4 (Human-in-the-loop) logic will go here.
-                                                } else {
-                                                    logs.push(format!(
-                                                        "- ✅ Found {} matching file(s):",
-                                                        files.len()
-                                                    ));
-
-                                                    if files.len() == 1 {
-                                                        let file_to_attach = &files[0];
-                                                        logs.push(format!(
-                                                            "- ✅ Found one clear match: '{}'. Proceeding to draft email with attachment.",
-                                                            file_to_attach.name
-                                                        ));
-
-                                                        let (
-                                                            file_data_result,
-                                                            attachment_filename,
-                                                            attachment_mime_type,
-                                                        ) = {
-                                                            let mime_type =
-                                                                &file_to_attach.mime_type;
-                                                            match mime_type.as_str() {
-                                                                // Google Docs
-                                                                "application/vnd.google-apps.document" => {
-                                                                    let export_mime_type = "application/vnd.openxmlformats-officedocument.wordprocessingml.document";
-                                                                    let filename = format!("{}.docx", file_to_attach.name);
-                                                                    logs.push(format!("- File is a Google Doc, exporting as MS Word (.docx)."));
-                                                                    (
-                                                                        drive::client::export_file(&access_token, &file_to_attach.id, export_mime_type).await,
-                                                                        filename,
-                                                                        export_mime_type.to_string(),
-                                                                    )
-                                                                }
-                                                                // Google Sheets
-                                                                "application/vnd.google-apps.spreadsheet" => {
-                                                                    let export_mime_type = "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet";
-                                                                    let filename = format!("{}.xlsx", file_to_attach.name);
-                                                                    logs.push(format!("- File is a Google Sheet, exporting as MS Excel (.xlsx)."));
-                                                                    (
-                                                                        drive::client::export_file(&access_token, &file_to_attach.id, export_mime_type).await,
-                                                                        filename,
-                                                                        export_mime_type.to_string(),
-                                                                    )
-                                                                }
-                                                                // Google Slides
-                                                                "application/vnd.google-apps.presentation" => {
-                                                                    let export_mime_type = "application/vnd.openxmlformats-officedocument.presentationml.presentation";
-                                                                    let filename = format!("{}.pptx", file_to_attach.name);
-                                                                    logs.push(format!("- File is a Google Slide, exporting as MS PowerPoint (.pptx)."));
-                                                                    (
-                                                                        drive::client::export_file(&access_token, &file_to_attach.id, export_mime_type).await,
-                                                                        filename,
-                                                                        export_mime_type.to_string(),
-                                                                    )
-                                                                }
-                                                                // For other native Google types or any other file, download directly.
-                                                                _ => {
-                                                                    logs.push(format!("- File is a standard type ('{}'), downloading directly.", mime_type));
-                                                                    (
-                                                                        drive::client::download_file(&access_token, &file_to_attach.id).await,
-                                                                        file_to_attach.name.clone(),
-                                                                        file_to_attach.mime_type.clone(),
-                                                                    )
-                                                                }
-                                                            }
-                                                        };
-
-                                                        match file_data_result {
-                                                            Ok(file_data) => {
-                                                                let mut to_recipients: Vec<&str> = from.split(',').chain(to.split(',')).collect();
-                                                                let mut cc_recipients: Vec<&str> = cc.split(',').collect();
-
-                                                                to_recipients.retain(|email| !email.contains(&user_email) && !email.trim().is_empty());
-                                                                cc_recipients.retain(|email| !email.contains(&user_email) && !email.trim().is_empty());
-                                                                to_recipients.sort();
-                                                                to_recipients.dedup();
-                                                                let to_all = to_recipients.join(", ");
-
-                                                                cc_recipients.sort();
-                                                                cc_recipients.dedup();
-                                                                let cc_all = cc_recipients.join(", ");
-
-                                                                let draft_prompt =
-                                                                    gemini::prompts::get_drafting_prompt(from, subject, &body, Some(attachment_filename.clone()));
-
-                                                                match gemini::client::call_gemini(&gemini_api_key, &draft_prompt)
-                                                                    .await
-                                                                {
-                                                                    Ok(draft_text) => {
-                                                                        logs.push(format!("- Draft from Gemini: {}", draft_text));
-
-                                                                        let attachment = Some(models::Attachment {
-                                                                            filename: attachment_filename,
-                                                                            mime_type: attachment_mime_type,
-                                                                            data: file_data,
-                                                                        });
-
-                                                                        match gmail::client::create_draft_with_attachment(
-                                                                            &access_token,
-                                                                            &user_email,
-                                                                            &message_id.thread_id,
-                                                                            &to_all,
-                                                                            &cc_all,
-                                                                            subject,
-                                                                            &draft_text,
-                                                                            attachment,
-                                                                        )
-                                                                        .await {
-                                                                            Ok(_) => {
-                                                                                logs.push("- Successfully created draft in Gmail.".to_string());
-                                                                                match gmail::client::mark_as_read(&access_token, &user_email, &message_id.id).await {
-                                                                                    Ok(_) => logs.push("- Successfully marked original email as read.".to_string()),
-                                                                                    Err(e) => logs.push(format!("- Failed to mark email as read: {}", e)),
-                                                                                };
-                                                                            },
-                                                                            Err(e) => logs.push(format!("- Failed to create draft: {}", e)),
-                                                                        }
-                                                                    }
-                                                                    Err(e) => logs.push(format!("- Failed to generate draft from Gemini: {}", e)),
-                                                                }
-                                                            },
-                                                            Err(e) => logs.push(format!("- Failed to download or export file '{}': {}", file_to_attach.name, e)),
-                                                        }
-                                                    } else {
-                                                        logs.push("- ⚠️ Multiple files found, this is not yet handled.".to_string());
-                                                        for file in files {
-                                                            logs.push(format!(
-                                                                "- Name: {}, Link: {}",
-                                                                file.name, file.web_view_link
-                                                            ));
-                                                        }
-                                                        // FUTURE: Phase 4 (Human-in-the-loop) logic will go here to allow user to select a file.
-                                                    }
-                                                }
-                                            }
-                                            Err(e) => {
-                                                logs.push(format!(
-                                                    "- ❌ Error during Google Drive search: {}",
-                                                    e
-                                                ));
-                                            }
-                                        }
-                                    }
-                                    Err(e) => {
-                                        logs.push(format!(
-                                            "- ❌ Error getting search keywords from Gemini: {}",
-                                            e
-                                        ));
-                                    }
-                                }
-                            }
+                    match process_message(&ctx, i, message_id, logs).await {
+                        Ok(Some(state)) => stats.count(state),
+                        Ok(None) => {}
+                        Err(e) => {
+                            logs.push(format!(
+                                "Stopping this run: {}. Remaining emails are left for the next run.",
+                                e
+                            ));
+                            break;
                         }
+                    }
+                }
+            }
+        }
+        Err(e) => return Err(Error::from(format!("Failed to fetch emails: {}", e))),
+    }
+
+    Ok(())
+}
+
+/// Collects up to `max_messages_per_run` messages still to handle. Listing
+/// always starts from the top: handled mail drops out of the listing (see
+/// `MailProvider::list_unread`), so the next run picks up where this one
+/// stopped and new mail is never held back behind an old backlog.
+async fn list_unread_batch(
+    ctx: &PipelineContext,
+) -> std::result::Result<Vec<models::MessageId>, error::ApiError> {
+    let mut page_token: Option<String> = None;
+    let mut messages: Vec<models::MessageId> = Vec::new();
+
+    while messages.len() < ctx.max_messages_per_run {
+        // Gmail caps `maxResults` at 500.
+        let remaining = (ctx.max_messages_per_run - messages.len()).min(500) as u32;
+        let page = ctx
+            .mail
+            .list_unread(page_token.as_deref(), remaining)
+            .await?;
+
+        messages.extend(page.messages.unwrap_or_default());
+        page_token = page.next_page_token;
+        if page_token.is_none() {
+            break;
+        }
+    }
+
+    Ok(messages)
+}
+
+/// Errors after which processing further messages in the same run is
+/// pointless: the credentials are gone or the quota is used up.
+fn halts_run(error: &error::ApiError) -> bool {
+    error.is_auth_expired() || error.is_quota_exceeded()
+}
+
+/// Classifies a single message and, depending on the decision, drafts a reply
+/// (optionally with a Drive attachment). Messages already in the ledger, or
+/// claimed by a concurrent run, are skipped, so each one gets at most one
+/// draft. Returns the state the message
+/// was left in, or `None` when it was not processed or is left for the next
+/// run. Failures, including those to read the ledger or take the claim, go
+/// through `mark_failed` and are recorded in
+/// `logs`; only errors that should stop the whole run (see `halts_run`) are
+/// returned.
+pub async fn process_message(
+    ctx: &PipelineContext,
+    i: usize,
+    message_id: &models::MessageId,
+    logs: &mut Vec<String>,
+) -> std::result::Result<Option<models::BotLabel>, error::ApiError> {
+    match ctx.ledger.lookup(&message_id.id).await {
+        Ok(Some(entry)) => {
+            logs.push(format!(
+                "\n===== Email #{} ===== already processed ({:?} at {}), skipping.",
+                i + 1,
+                entry.action,
+                entry.processed_at
+            ));
+            // Still listed, so labelling it failed last time.
+            apply_state(ctx, &message_id.id, entry.action.into(), logs).await;
+            return Ok(None);
+        }
+        Ok(None) => {}
+        Err(e) => {
+            logs.push(format!(
+                "Could not read ledger for message {}: {}",
+                message_id.id, e
+            ));
+            // Storage errors are usually transient.
+            return Ok(mark_failed(ctx, &message_id.id, None, true, logs).await);
+        }
+    }
+
+    match ctx.ledger.claim(&message_id.id).await {
+        Ok(true) => {}
+        Ok(false) => {
+            logs.push(format!(
+                "\n===== Email #{} ===== being processed by another run, skipping.",
+                i + 1
+            ));
+            return Ok(None);
+        }
+        Err(e) => {
+            logs.push(format!("Could not claim message {}: {}", message_id.id, e));
+            return Ok(mark_failed(ctx, &message_id.id, None, true, logs).await);
+        }
+    }
+
+    let result = handle_message(ctx, i, message_id, logs).await;
+    // Handled messages keep their claim until it expires; see
+    // `ledger::prune_claims`.
+    if !matches!(&result, Ok(Some(state)) if state.is_final()) {
+        if let Err(e) = ctx.ledger.release(&message_id.id).await {
+            logs.push(format!("- ⚠️ Failed to release claim: {}", e));
+        }
+    }
+    result
+}
+
+/// Does the work of `process_message` on a message this run has claimed.
+async fn handle_message(
+    ctx: &PipelineContext,
+    i: usize,
+    message_id: &models::MessageId,
+    logs: &mut Vec<String>,
+) -> std::result::Result<Option<models::BotLabel>, error::ApiError> {
+    match ctx.mail.get_message(&message_id.id).await {
+        Ok(details) => {
+            let from = details
+                .payload
+                .headers
+                .iter()
+                .find(|h| h.name == "From")
+                .map_or("Unknown Sender", |h| &h.value);
+            let subject = details
+                .payload
+                .headers
+                .iter()
+                .find(|h| h.name == "Subject")
+                .map_or("No Subject", |h| &h.value);
+            let header = |name: &str| {
+                details
+                    .payload
+                    .headers
+                    .iter()
+                    .find(|h| h.name.eq_ignore_ascii_case(name))
+                    .map(|h| h.value.clone())
+            };
+            let original_message_id = header("Message-ID");
+            let original_references = header("References");
+            let original_text = mail::body::extract_text(&details.payload);
+            let body = original_text
+                .clone()
+                .unwrap_or_else(|| "No readable body found".to_string());
+
+            let classification_prompt =
+                gemini::prompts::get_classification_prompt(from, subject, &body, &ctx.settings);
+
+            logs.push(format!("\n===== Email #{} =====", i + 1));
+            logs.push(format!("- Subject: {}", subject));
+
+            let classification = match ctx
+                .llm
+                .generate_json(
+                    &classification_prompt,
+                    &gemini::prompts::get_classification_schema(),
+                )
+                .await
+            {
+                Ok(text) => match models::Classification::from_llm_output(&text) {
+                    Ok(classification) => Ok(classification),
+                    Err(e) => {
+                        logs.push(format!(
+                            "- ⚠️ Unparseable classification ({}): {}",
+                            e,
+                            text.trim()
+                        ));
+                        Err(false)
+                    }
+                },
+                Err(e) if halts_run(&e) => return Err(e),
+                Err(e) => {
+                    logs.push(format!("- ❌ Classification failed: {}", e));
+                    Err(e.retryable)
+                }
+            };
+
+            let classification = match classification {
+                Ok(classification) => {
+                    logs.push(format!(
+                        "- Intent: {:?} (confidence {:.2}, language {}): {}",
+                        classification.intent,
+                        classification.confidence,
+                        classification.language,
+                        classification.reason
+                    ));
+                    classification
+                }
+                Err(retryable) => {
+                    logs.push("- Could not classify email, leaving it unread.".to_string());
+                    return Ok(mark_failed(ctx, &message_id.id, None, retryable, logs).await);
+                }
+            };
+
+            let sender_email = mail::address::parse_address_list(from)
+                .into_iter()
+                .next()
+                .map(|address| address.email)
+                .unwrap_or_default();
+            let policy = ctx
+                .settings
+                .recipient_policies
+                .select(classification.intent, &sender_email);
+            let recipients = mail::recipients::compute_reply_recipients(
+                &details.payload.headers,
+                &ctx.own_addresses,
+                policy,
+            );
+
+            // Reply from the alias the message was sent to, with its signature.
+            let alias = mail::signature::select_alias(&ctx.send_as, &details.payload.headers);
+            let reply = models::DraftReply {
+                thread_id: message_id.thread_id.clone(),
+                from: alias.map(|alias| {
+                    mail::address::Address {
+                        name: alias.display_name.clone().filter(|name| !name.is_empty()),
+                        email: alias.send_as_email.clone(),
+                    }
+                    .to_string()
+                }),
+                to: mail::address::format_address_list(&recipients.to),
+                cc: mail::address::format_address_list(&recipients.cc),
+                subject: mail::subject::reply_subject(subject),
+                body: String::new(),
+                html_body: None,
+                attachments: Vec::new(),
+                in_reply_to: original_message_id,
+                references: original_references,
+            };
+            let original = mail::quote::QuotedOriginal {
+                date: header("Date"),
+                sender: from.to_string(),
+                text: original_text.unwrap_or_default(),
+            };
+            let signature = mail::signature::Signature::for_reply(alias, &ctx.settings);
+
+            let state = if classification.intent == models::Intent::Reply {
+                logs.push("- Intent is REPLY. Drafting reply...".to_string());
+                logs.push(format!("- Recipient policy: {:?}", policy));
+
+                let (body, transcript) = thread_context(ctx, message_id, &body, logs).await;
+                let context = retrieve_context(ctx, subject, &body, logs).await;
+                let draft_prompt = gemini::prompts::get_drafting_prompt(
+                    from,
+                    subject,
+                    &body,
+                    &transcript,
+                    &context,
+                    &[],
+                    &ctx.settings,
+                );
+                draft_reply(
+                    ctx,
+                    message_id,
+                    &classification,
+                    &draft_prompt,
+                    reply,
+                    &original,
+                    &signature,
+                    logs,
+                )
+                .await?
+            } else if classification.intent == models::Intent::FileRequest {
+                logs.push(
+                    "- ✅ INTENT: File Request Detected. Proceeding to file research..."
+                        .to_string(),
+                );
+
+                let files = match find_requested_files(ctx, &body, logs).await {
+                    Ok(Some(files)) => files,
+                    Ok(None) => {
+                        // FUTURE: Phase 4 (Human-in-the-loop) logic will go here to allow user to select a file.
+                        return Ok(Some(
+                            record_outcome(
+                                ctx,
+                                &message_id.id,
+                                &classification,
+                                models::LedgerAction::NeedsFile,
+                                None,
+                                logs,
+                            )
+                            .await,
+                        ));
+                    }
+                    Err(e) if halts_run(&e) => return Err(e),
+                    Err(e) => {
+                        logs.push(format!("- ❌ Error while searching for files: {}", e));
+                        return Ok(mark_failed(
+                            ctx,
+                            &message_id.id,
+                            Some(&classification),
+                            e.retryable,
+                            logs,
+                        )
+                        .await);
+                    }
+                };
+
+                let mut attachments = Vec::new();
+                for file in &files {
+                    match fetch_attachment(ctx, file, logs).await {
+                        Ok(attachment) => attachments.push(attachment),
+                        Err(e) if halts_run(&e) => return Err(e),
                         Err(e) => {
                             logs.push(format!(
-                                "Error fetching details for message {}: {}",
-                                message_id.id, e
+                                "- Failed to download or export file '{}': {}",
+                                file.name, e
                             ));
+                            return Ok(mark_failed(
+                                ctx,
+                                &message_id.id,
+                                Some(&classification),
+                                e.retryable,
+                                logs,
+                            )
+                            .await);
                         }
                     }
                 }
+
+                let attachment_names: Vec<String> =
+                    attachments.iter().map(|a| a.filename.clone()).collect();
+                let (body, transcript) = thread_context(ctx, message_id, &body, logs).await;
+                let context = retrieve_context(ctx, subject, &body, logs).await;
+                let draft_prompt = gemini::prompts::get_drafting_prompt(
+                    from,
+                    subject,
+                    &body,
+                    &transcript,
+                    &context,
+                    &attachment_names,
+                    &ctx.settings,
+                );
+                let reply = models::DraftReply {
+                    attachments,
+                    ..reply
+                };
+                draft_reply(
+                    ctx,
+                    message_id,
+                    &classification,
+                    &draft_prompt,
+                    reply,
+                    &original,
+                    &signature,
+                    logs,
+                )
+                .await?
+            } else {
+                Some(
+                    record_outcome(
+                        ctx,
+                        &message_id.id,
+                        &classification,
+                        models::LedgerAction::Skipped,
+                        None,
+                        logs,
+                    )
+                    .await,
+                )
+            };
+            Ok(state)
+        }
+        Err(e) if halts_run(&e) => Err(e),
+        Err(e) if e.is_not_found() => {
+            logs.push(format!(
+                "Message {} no longer exists, skipping.",
+                message_id.id
+            ));
+            Ok(None)
+        }
+        Err(e) => {
+            logs.push(format!(
+                "Error fetching details for message {}: {}",
+                message_id.id, e
+            ));
+            Ok(mark_failed(ctx, &message_id.id, None, e.retryable, logs).await)
+        }
+    }
+}
+
+/// The body to draft from and a transcript of the earlier messages in the
+/// thread. With a transcript the body's own quoted history is dropped, as the
+/// transcript already covers it. Without the thread, drafting goes ahead on
+/// the message alone.
+async fn thread_context(
+    ctx: &PipelineContext,
+    message_id: &models::MessageId,
+    body: &str,
+    logs: &mut Vec<String>,
+) -> (String, String) {
+    let transcript = match ctx.mail.get_thread(&message_id.thread_id).await {
+        Ok(messages) => mail::thread::transcript(&messages, &message_id.id, THREAD_TOKEN_BUDGET),
+        Err(e) => {
+            logs.push(format!(
+                "- Could not fetch thread {}, drafting from this message only: {}",
+                message_id.thread_id, e
+            ));
+            String::new()
+        }
+    };
+    if transcript.is_empty() {
+        return (body.to_string(), transcript);
+    }
+
+    logs.push(format!(
+        "- Thread context: ~{} tokens of earlier messages.",
+        mail::thread::estimate_tokens(&transcript)
+    ));
+    let stripped = mail::thread::strip_quoted(body);
+    let body = if stripped.is_empty() {
+        body.to_string()
+    } else {
+        stripped
+    };
+    (body, transcript)
+}
+
+/// Knowledge snippets similar to the message, from the account's part of
+/// the context index. Retrieval is best effort: when it fails the draft is
+/// written without them.
+async fn retrieve_context(
+    ctx: &PipelineContext,
+    subject: &str,
+    body: &str,
+    logs: &mut Vec<String>,
+) -> Vec<models::ContextualDocument> {
+    let Some(index) = &ctx.context_index else {
+        return Vec::new();
+    };
+
+    let query: String = format!("{}\n\n{}", subject, body)
+        .chars()
+        .take(CONTEXT_QUERY_CHARS)
+        .collect();
+    let vector = match ctx.llm.embed(&query).await {
+        Ok(vector) => vector,
+        Err(e) => {
+            logs.push(format!(
+                "- Could not embed email for context retrieval: {}",
+                e
+            ));
+            return Vec::new();
+        }
+    };
+
+    match index
+        .query(&ctx.context_namespace, &vector, CONTEXT_TOP_K)
+        .await
+    {
+        Ok(matches) => {
+            let documents: Vec<models::ContextualDocument> = matches
+                .into_iter()
+                .filter(|m| m.score >= CONTEXT_MIN_SCORE)
+                .map(|m| m.document)
+                .collect();
+            logs.push(format!(
+                "- Retrieved {} context document(s).",
+                documents.len()
+            ));
+            documents
+        }
+        Err(e) => {
+            logs.push(format!("- Could not query context index: {}", e));
+            Vec::new()
+        }
+    }
+}
+
+/// Generates the reply text for `draft_prompt`, saves it as a draft with the
+/// addressing and attachments of `reply`, followed by `signature` and
+/// `original` quoted below it, and records the outcome. Failures are left to
+/// a later run (see `mark_failed`), except those that halt the run (see
+/// `halts_run`), which are returned. Returns the message's new state, if it
+/// changed.
+#[allow(clippy::too_many_arguments)]
+async fn draft_reply(
+    ctx: &PipelineContext,
+    message_id: &models::MessageId,
+    classification: &models::Classification,
+    draft_prompt: &str,
+    reply: models::DraftReply,
+    original: &mail::quote::QuotedOriginal,
+    signature: &mail::signature::Signature,
+    logs: &mut Vec<String>,
+) -> std::result::Result<Option<models::BotLabel>, error::ApiError> {
+    let draft_text = match ctx.llm.generate(draft_prompt).await {
+        Ok(draft_text) => draft_text,
+        Err(e) if halts_run(&e) => return Err(e),
+        Err(e) => {
+            logs.push(format!("- Failed to generate draft from LLM: {}", e));
+            return Ok(
+                mark_failed(ctx, &message_id.id, Some(classification), e.retryable, logs).await,
+            );
+        }
+    };
+    logs.push(format!("- Draft from LLM: {}", draft_text));
+
+    let reply = models::DraftReply {
+        body: original.plain_reply(&draft_text, signature),
+        html_body: Some(original.html_reply(&draft_text, signature)),
+        ..reply
+    };
+    match ctx.mail.create_draft(&reply).await {
+        Ok(draft_id) => {
+            logs.push("- Successfully created draft in Gmail.".to_string());
+            Ok(Some(
+                record_outcome(
+                    ctx,
+                    &message_id.id,
+                    classification,
+                    models::LedgerAction::Drafted,
+                    Some(draft_id),
+                    logs,
+                )
+                .await,
+            ))
+        }
+        Err(e) if halts_run(&e) => Err(e),
+        Err(e) => {
+            logs.push(format!("- Failed to create draft: {}", e));
+            Ok(mark_failed(ctx, &message_id.id, Some(classification), e.retryable, logs).await)
+        }
+    }
+}
+
+/// Asks the model which documents the email requests and searches Drive for
+/// each one. Returns one file per document, or `None` when a document has no
+/// match or several, leaving the choice to the user.
+async fn find_requested_files(
+    ctx: &PipelineContext,
+    body: &str,
+    logs: &mut Vec<String>,
+) -> std::result::Result<Option<Vec<models::DriveFile>>, error::ApiError> {
+    let keywords_prompt = gemini::prompts::get_search_keywords_prompt(body);
+    let text = ctx
+        .llm
+        .generate_json(
+            &keywords_prompt,
+            &gemini::prompts::get_search_keywords_schema(),
+        )
+        .await?;
+    let requested = models::RequestedDocuments::from_llm_output(&text).map_err(|e| {
+        error::ApiError::parse(
+            error::Api::Gemini,
+            format!("Unparseable document list ({}): {}", e, text.trim()),
+        )
+    })?;
+
+    if requested.documents.is_empty() {
+        logs.push("- ⚠️ Could not tell which documents are requested.".to_string());
+        return Ok(None);
+    }
+
+    let mut found: Vec<models::DriveFile> = Vec::new();
+    let mut complete = true;
+    for document in &requested.documents {
+        // Match any keyword; quotes and backslashes must be escaped in Drive queries.
+        let query = document
+            .keywords
+            .iter()
+            .map(|s| s.trim())
+            .filter(|s| !s.is_empty())
+            .map(|word| {
+                format!(
+                    "name contains '{}'",
+                    word.replace('\\', "\\\\").replace('\'', "\\'")
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(" or ");
+        if query.is_empty() {
+            logs.push(format!("- ⚠️ No keywords for '{}'.", document.name));
+            complete = false;
+            continue;
+        }
+
+        logs.push(format!(
+            "- Searching Drive for '{}' with query: {}",
+            document.name, query
+        ));
+        let files = ctx.drive.search_files(&query).await?;
+        match files.as_slice() {
+            [] => {
+                logs.push(format!("- ⚠️ No files found for '{}'.", document.name));
+                complete = false;
+            }
+            [file] => {
+                logs.push(format!(
+                    "- ✅ Found one clear match for '{}': '{}'.",
+                    document.name, file.name
+                ));
+                if !found.iter().any(|f| f.id == file.id) {
+                    found.push(file.clone());
+                }
+            }
+            files => {
+                logs.push(format!(
+                    "- ⚠️ Found {} files for '{}', not sure which one to attach:",
+                    files.len(),
+                    document.name
+                ));
+                for file in files {
+                    logs.push(format!(
+                        "- Name: {}, Link: {}",
+                        file.name, file.web_view_link
+                    ));
+                }
+                complete = false;
             }
         }
-        Err(e) => return Response::error(format!("Failed to fetch emails: {}", e), 500),
     }
 
-    Response::ok(logs.join("\n"))
+    Ok(complete.then_some(found))
+}
+
+/// Downloads a Drive file for attaching. Google Docs, Sheets and Slides are
+/// exported to their Microsoft Office equivalents.
+async fn fetch_attachment(
+    ctx: &PipelineContext,
+    file: &models::DriveFile,
+    logs: &mut Vec<String>,
+) -> std::result::Result<models::Attachment, error::ApiError> {
+    let export = match file.mime_type.as_str() {
+        "application/vnd.google-apps.document" => Some((
+            "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+            "docx",
+            "Google Doc",
+            "MS Word",
+        )),
+        "application/vnd.google-apps.spreadsheet" => Some((
+            "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+            "xlsx",
+            "Google Sheet",
+            "MS Excel",
+        )),
+        "application/vnd.google-apps.presentation" => Some((
+            "application/vnd.openxmlformats-officedocument.presentationml.presentation",
+            "pptx",
+            "Google Slide",
+            "MS PowerPoint",
+        )),
+        // For other native Google types or any other file, download directly.
+        _ => None,
+    };
+
+    match export {
+        Some((export_mime_type, extension, kind, office)) => {
+            logs.push(format!(
+                "- '{}' is a {}, exporting as {} (.{}).",
+                file.name, kind, office, extension
+            ));
+            Ok(models::Attachment {
+                filename: format!("{}.{}", file.name, extension),
+                mime_type: export_mime_type.to_string(),
+                data: ctx.drive.export_file(&file.id, export_mime_type).await?,
+            })
+        }
+        None => {
+            logs.push(format!(
+                "- '{}' is a standard type ('{}'), downloading directly.",
+                file.name, file.mime_type
+            ));
+            Ok(models::Attachment {
+                filename: file.name.clone(),
+                mime_type: file.mime_type.clone(),
+                data: ctx.drive.download_file(&file.id).await?,
+            })
+        }
+    }
 }
 
-fn find_plain_text_body(payload: &models::MessagePart) -> Option<String> {
-    if payload.mime_type == "text/plain" {
-        if let Some(data) = &payload.body.data {
-            return Some(data.clone());
+/// Handles a message that could not be processed. A `retryable` failure (a
+/// network error, a 5xx or a rate limit) is left unlabelled, as the next run
+/// is likely to succeed, and anything else is labelled `Bot/Error` for the
+/// user to look at. The two are counted apart: after `MAX_ATTEMPTS`
+/// permanent or `MAX_TEMPORARY_FAILURES` temporary failures the message is
+/// recorded and labelled `Bot/Failed`, which takes it out of the listing for
+/// good. Returns the new state, if it changed.
+async fn mark_failed(
+    ctx: &PipelineContext,
+    message_id: &str,
+    classification: Option<&models::Classification>,
+    retryable: bool,
+    logs: &mut Vec<String>,
+) -> Option<models::BotLabel> {
+    let attempts = match ctx.ledger.record_failure(message_id, retryable).await {
+        Ok(attempts) => attempts,
+        Err(e) => {
+            logs.push(format!("- ⚠️ Failed to count the failed attempt: {}", e));
+            0
         }
+    };
+
+    let limit = if retryable {
+        MAX_TEMPORARY_FAILURES
+    } else {
+        MAX_ATTEMPTS
+    };
+    if attempts >= limit {
+        logs.push(format!(
+            "- Failed {} times, giving up on this email.",
+            attempts
+        ));
+        if let Err(e) = ctx
+            .ledger
+            .record(
+                message_id,
+                classification,
+                models::LedgerAction::Failed,
+                None,
+            )
+            .await
+        {
+            logs.push(format!("- ⚠️ Failed to record message in ledger: {}", e));
+        }
+        apply_state(ctx, message_id, models::BotLabel::Failed, logs).await;
+        return Some(models::BotLabel::Failed);
+    }
+    if retryable {
+        logs.push("- Temporary failure, leaving the email for the next run.".to_string());
+        return None;
     }
+    apply_state(ctx, message_id, models::BotLabel::Error, logs).await;
+    Some(models::BotLabel::Error)
+}
 
-    if let Some(parts) = &payload.parts {
-        for part in parts {
-            if let Some(body) = find_plain_text_body(part) {
-                return Some(body);
+/// Writes the message's ledger entry and moves it to the matching `Bot/*`
+/// label, which it returns. Failures are only logged: the work is already
+/// done, and the ledger keeps a later run from repeating it.
+async fn record_outcome(
+    ctx: &PipelineContext,
+    message_id: &str,
+    classification: &models::Classification,
+    action: models::LedgerAction,
+    draft_id: Option<String>,
+    logs: &mut Vec<String>,
+) -> models::BotLabel {
+    if let Err(e) = ctx
+        .ledger
+        .record(message_id, Some(classification), action, draft_id)
+        .await
+    {
+        logs.push(format!("- ⚠️ Failed to record message in ledger: {}", e));
+    }
+    let state = action.into();
+    apply_state(ctx, message_id, state, logs).await;
+    state
+}
+
+/// Labels the message with `state` and removes the other `Bot/*` labels. In a
+/// final state `UNREAD` is removed as well if the account's `mark_as_read`
+/// setting asks for it.
+async fn apply_state(
+    ctx: &PipelineContext,
+    message_id: &str,
+    state: models::BotLabel,
+    logs: &mut Vec<String>,
+) {
+    let result = async {
+        let add = ctx.mail.label_id(state.name()).await?;
+        let mut remove = Vec::new();
+        for other in models::BotLabel::ALL.iter().filter(|l| **l != state) {
+            remove.push(ctx.mail.label_id(other.name()).await?);
+        }
+        if state.is_final() && ctx.settings.mark_as_read {
+            remove.push("UNREAD".to_string());
+        }
+
+        let remove: Vec<&str> = remove.iter().map(String::as_str).collect();
+        ctx.mail
+            .modify_labels(message_id, &[add.as_str()], &remove)
+            .await
+    }
+    .await;
+
+    match result {
+        Ok(_) => logs.push(format!("- Labelled email {}.", state.name())),
+        Err(e) => logs.push(format!("- Failed to label email {}: {}", state.name(), e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_trait::async_trait;
+    use drive::fake::FakeDriveProvider;
+    use ledger::fake::FakeLedger;
+    use ledger::Ledger;
+    use mail::fake::FakeMailProvider;
+    use std::future::Future;
+    use std::pin::pin;
+    use std::task::{Context, Poll, Waker};
+
+    /// Runs a future that never waits on I/O: the fakes answer immediately.
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let mut future = pin!(future);
+        let mut cx = Context::from_waker(Waker::noop());
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                return output;
+            }
+        }
+    }
+
+    /// Answers the classification prompt with `classification`, the keywords
+    /// prompt with `documents` and drafting prompts with `draft`.
+    struct StubLlm {
+        classification: String,
+        documents: String,
+        draft: std::result::Result<String, error::ApiError>,
+    }
+
+    #[async_trait(?Send)]
+    impl llm::LlmClient for StubLlm {
+        async fn generate(&self, _prompt: &str) -> std::result::Result<String, error::ApiError> {
+            self.draft.clone()
+        }
+
+        async fn generate_json(
+            &self,
+            _prompt: &str,
+            schema: &serde_json::Value,
+        ) -> std::result::Result<String, error::ApiError> {
+            if schema["properties"].get("documents").is_some() {
+                Ok(self.documents.clone())
+            } else {
+                Ok(self.classification.clone())
             }
         }
+
+        async fn embed(&self, _text: &str) -> std::result::Result<Vec<f32>, error::ApiError> {
+            Ok(vec![0.0; 8])
+        }
+
+        fn embedding_model(&self) -> &str {
+            "stub-embedding"
+        }
+    }
+
+    struct Harness {
+        mail: FakeMailProvider,
+        ledger: FakeLedger,
+        ctx: PipelineContext,
+    }
+
+    fn classification(intent: &str) -> String {
+        format!(
+            r#"{{"intent": "{}", "confidence": 0.9, "language": "en", "reason": "test"}}"#,
+            intent
+        )
+    }
+
+    /// A mailbox holding message `m1` from Jane, with `drive` as the Drive.
+    fn harness(llm: StubLlm, drive: FakeDriveProvider) -> Harness {
+        let mail = FakeMailProvider::new();
+        mail.add_send_as("me@example.com", Some("Me Example"));
+        mail.add_message(
+            "m1",
+            "t1",
+            &[
+                ("From", "Jane Doe <jane@example.com>"),
+                ("To", "me@example.com"),
+                ("Subject", "Lunch"),
+                ("Message-ID", "<m1@example.com>"),
+            ],
+            "Can we meet for lunch on Friday?",
+        );
+        let ledger = FakeLedger::new();
+        let send_as = block_on(mail::MailProvider::list_send_as(&mail)).unwrap();
+        let ctx = PipelineContext::new(
+            "me@example.com",
+            Box::new(mail.clone()),
+            Box::new(drive),
+            Box::new(ledger.clone()),
+            Box::new(llm),
+            models::AccountSettings::default(),
+            send_as,
+        );
+        Harness { mail, ledger, ctx }
+    }
+
+    fn stub(intent: &str) -> StubLlm {
+        StubLlm {
+            classification: classification(intent),
+            documents: r#"{"documents": []}"#.to_string(),
+            draft: Ok("Sure, see you then.".to_string()),
+        }
+    }
+
+    fn process(harness: &Harness) -> Option<models::BotLabel> {
+        let message_id = models::MessageId {
+            id: "m1".to_string(),
+            thread_id: "t1".to_string(),
+        };
+        let mut logs = Vec::new();
+        block_on(process_message(&harness.ctx, 0, &message_id, &mut logs)).unwrap()
     }
 
-    None
+    #[test]
+    fn reply_is_drafted_and_recorded() {
+        let harness = harness(stub("REPLY"), FakeDriveProvider::new());
+
+        assert_eq!(process(&harness), Some(models::BotLabel::Drafted));
+
+        let drafts = harness.mail.drafts();
+        assert_eq!(drafts.len(), 1);
+        assert_eq!(
+            drafts[0].from.as_deref(),
+            Some("Me Example <me@example.com>")
+        );
+        assert_eq!(drafts[0].to, "Jane Doe <jane@example.com>");
+        assert_eq!(drafts[0].subject, "Re: Lunch");
+        assert_eq!(drafts[0].in_reply_to.as_deref(), Some("<m1@example.com>"));
+        assert!(drafts[0].body.starts_with("Sure, see you then."));
+        let entry = harness.ledger.get("m1").unwrap();
+        assert_eq!(entry.action, models::LedgerAction::Drafted);
+        assert_eq!(entry.draft_id.as_deref(), Some("draft-1"));
+        let stored = serde_json::to_value(&entry).unwrap();
+        let fields: Vec<&String> = stored["classification"]
+            .as_object()
+            .unwrap()
+            .keys()
+            .collect();
+        assert_eq!(fields, ["confidence", "intent"]);
+        assert!(harness
+            .mail
+            .labels("m1")
+            .contains(&"Bot/Drafted".to_string()));
+    }
+
+    #[test]
+    fn no_reply_is_skipped_without_a_draft() {
+        let harness = harness(stub("NO_REPLY"), FakeDriveProvider::new());
+
+        assert_eq!(process(&harness), Some(models::BotLabel::Skipped));
+
+        assert!(harness.mail.drafts().is_empty());
+        let entry = harness.ledger.get("m1").unwrap();
+        assert_eq!(entry.action, models::LedgerAction::Skipped);
+        assert!(harness
+            .mail
+            .labels("m1")
+            .contains(&"Bot/Skipped".to_string()));
+    }
+
+    #[test]
+    fn file_request_attaches_the_matching_drive_file() {
+        let drive = FakeDriveProvider::new();
+        drive.add_file("f1", "Invoice March.pdf", "application/pdf", b"%PDF");
+        drive.add_file("f2", "Menu.pdf", "application/pdf", b"%PDF");
+        let llm = StubLlm {
+            classification: classification("FILE_REQUEST"),
+            documents: r#"{"documents": [{"name": "invoice", "keywords": ["Invoice"]}]}"#
+                .to_string(),
+            draft: Ok("Sure, see you then.".to_string()),
+        };
+        let harness = harness(llm, drive);
+
+        assert_eq!(process(&harness), Some(models::BotLabel::Drafted));
+
+        let drafts = harness.mail.drafts();
+        assert_eq!(drafts.len(), 1);
+        assert_eq!(drafts[0].attachments.len(), 1);
+        assert_eq!(drafts[0].attachments[0].filename, "Invoice March.pdf");
+        assert_eq!(drafts[0].attachments[0].data, b"%PDF");
+    }
+
+    #[test]
+    fn file_request_without_a_match_needs_a_file() {
+        let llm = StubLlm {
+            classification: classification("FILE_REQUEST"),
+            documents: r#"{"documents": [{"name": "invoice", "keywords": ["Invoice"]}]}"#
+                .to_string(),
+            draft: Ok("Sure, see you then.".to_string()),
+        };
+        let harness = harness(llm, FakeDriveProvider::new());
+
+        assert_eq!(process(&harness), Some(models::BotLabel::NeedsFile));
+
+        assert!(harness.mail.drafts().is_empty());
+        let entry = harness.ledger.get("m1").unwrap();
+        assert_eq!(entry.action, models::LedgerAction::NeedsFile);
+    }
+
+    #[test]
+    fn unparseable_classification_is_marked_error_and_not_recorded() {
+        let llm = StubLlm {
+            classification: "I think this needs a reply.".to_string(),
+            documents: String::new(),
+            draft: Ok(String::new()),
+        };
+        let harness = harness(llm, FakeDriveProvider::new());
+
+        assert_eq!(process(&harness), Some(models::BotLabel::Error));
+
+        assert!(harness.mail.drafts().is_empty());
+        assert!(harness.ledger.get("m1").is_none());
+        let labels = harness.mail.labels("m1");
+        assert!(labels.contains(&"Bot/Error".to_string()));
+        assert!(labels.contains(&"UNREAD".to_string()));
+    }
+
+    #[test]
+    fn message_in_the_ledger_is_not_drafted_again() {
+        let harness = harness(stub("REPLY"), FakeDriveProvider::new());
+        let classification =
+            models::Classification::from_llm_output(&classification("REPLY")).unwrap();
+        block_on(harness.ledger.record(
+            "m1",
+            Some(&classification),
+            models::LedgerAction::Drafted,
+            Some("draft-0".to_string()),
+        ))
+        .unwrap();
+
+        assert_eq!(process(&harness), None);
+
+        assert!(harness.mail.drafts().is_empty());
+        assert!(harness
+            .mail
+            .labels("m1")
+            .contains(&"Bot/Drafted".to_string()));
+    }
+
+    #[test]
+    fn temporary_failure_is_left_unlabelled_for_the_next_run() {
+        let llm = StubLlm {
+            draft: Err(error::ApiError::http(
+                error::Api::Gemini,
+                503,
+                "The model is overloaded.",
+            )),
+            ..stub("REPLY")
+        };
+        let harness = harness(llm, FakeDriveProvider::new());
+
+        assert_eq!(process(&harness), None);
+
+        assert!(harness.ledger.get("m1").is_none());
+        assert_eq!(harness.mail.labels("m1"), ["INBOX", "UNREAD"]);
+    }
+
+    #[test]
+    fn repeated_temporary_failures_are_not_given_up_on_early() {
+        let llm = StubLlm {
+            draft: Err(error::ApiError::http(
+                error::Api::Gemini,
+                503,
+                "The model is overloaded.",
+            )),
+            ..stub("REPLY")
+        };
+        let harness = harness(llm, FakeDriveProvider::new());
+
+        for _ in 1..MAX_TEMPORARY_FAILURES {
+            assert_eq!(process(&harness), None);
+        }
+        assert!(harness.ledger.get("m1").is_none());
+        assert_eq!(harness.mail.labels("m1"), ["INBOX", "UNREAD"]);
+
+        assert_eq!(process(&harness), Some(models::BotLabel::Failed));
+    }
+
+    #[test]
+    fn permanent_failure_is_marked_error() {
+        let llm = StubLlm {
+            draft: Err(error::ApiError::http(
+                error::Api::Gemini,
+                400,
+                "Request contains an invalid argument.",
+            )),
+            ..stub("REPLY")
+        };
+        let harness = harness(llm, FakeDriveProvider::new());
+
+        assert_eq!(process(&harness), Some(models::BotLabel::Error));
+
+        assert!(harness.ledger.get("m1").is_none());
+        assert!(harness.mail.labels("m1").contains(&"Bot/Error".to_string()));
+    }
+
+    #[test]
+    fn quota_error_while_drafting_halts_the_run() {
+        let llm = StubLlm {
+            draft: Err(error::ApiError::http(
+                error::Api::Gemini,
+                429,
+                "Resource has been exhausted.",
+            )),
+            ..stub("REPLY")
+        };
+        let harness = harness(llm, FakeDriveProvider::new());
+        let message_id = models::MessageId {
+            id: "m1".to_string(),
+            thread_id: "t1".to_string(),
+        };
+
+        let result = block_on(process_message(
+            &harness.ctx,
+            0,
+            &message_id,
+            &mut Vec::new(),
+        ));
+
+        assert!(result.is_err_and(|e| e.is_quota_exceeded()));
+        assert!(harness.ledger.get("m1").is_none());
+        assert_eq!(harness.mail.labels("m1"), ["INBOX", "UNREAD"]);
+        assert!(block_on(harness.ledger.claim("m1")).unwrap());
+    }
+
+    #[test]
+    fn message_is_given_up_on_after_repeated_failures() {
+        let llm = StubLlm {
+            classification: "I think this needs a reply.".to_string(),
+            ..stub("REPLY")
+        };
+        let harness = harness(llm, FakeDriveProvider::new());
+
+        for _ in 1..MAX_ATTEMPTS {
+            assert_eq!(process(&harness), Some(models::BotLabel::Error));
+        }
+        assert_eq!(process(&harness), Some(models::BotLabel::Failed));
+
+        let entry = harness.ledger.get("m1").unwrap();
+        assert_eq!(entry.action, models::LedgerAction::Failed);
+        assert!(entry.classification.is_none());
+        let labels = harness.mail.labels("m1");
+        assert!(labels.contains(&"Bot/Failed".to_string()));
+        assert!(!labels.contains(&"Bot/Error".to_string()));
+        let listed = block_on(mail::MailProvider::list_unread(&harness.mail, None, 10)).unwrap();
+        assert!(listed.messages.unwrap_or_default().is_empty());
+    }
+
+    #[test]
+    fn message_that_cannot_be_fetched_is_given_up_on() {
+        let harness = harness(stub("REPLY"), FakeDriveProvider::new());
+        harness.mail.fail_message(
+            "m1",
+            error::ApiError::http(error::Api::Gmail, 400, "Invalid id value"),
+        );
+
+        for _ in 1..MAX_ATTEMPTS {
+            assert_eq!(process(&harness), Some(models::BotLabel::Error));
+        }
+        assert_eq!(process(&harness), Some(models::BotLabel::Failed));
+
+        assert!(harness.mail.drafts().is_empty());
+        assert_eq!(
+            harness.ledger.get("m1").unwrap().action,
+            models::LedgerAction::Failed
+        );
+        let listed = block_on(mail::MailProvider::list_unread(&harness.mail, None, 10)).unwrap();
+        assert!(listed.messages.unwrap_or_default().is_empty());
+    }
+
+    #[test]
+    fn message_claimed_by_another_run_is_left_alone() {
+        let harness = harness(stub("REPLY"), FakeDriveProvider::new());
+        assert!(block_on(harness.ledger.claim("m1")).unwrap());
+
+        assert_eq!(process(&harness), None);
+
+        assert!(harness.mail.drafts().is_empty());
+        assert!(harness.ledger.get("m1").is_none());
+    }
+
+    #[test]
+    fn claim_is_kept_after_drafting() {
+        let harness = harness(stub("REPLY"), FakeDriveProvider::new());
+
+        assert_eq!(process(&harness), Some(models::BotLabel::Drafted));
+
+        assert!(!block_on(harness.ledger.claim("m1")).unwrap());
+    }
+
+    #[test]
+    fn claim_is_released_after_a_failure() {
+        let llm = StubLlm {
+            classification: "not json".to_string(),
+            ..stub("REPLY")
+        };
+        let harness = harness(llm, FakeDriveProvider::new());
+
+        assert_eq!(process(&harness), Some(models::BotLabel::Error));
+
+        assert!(block_on(harness.ledger.claim("m1")).unwrap());
+    }
 }
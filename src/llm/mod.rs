@@ -0,0 +1,71 @@
+pub mod openai;
+
+use crate::error::ApiError;
+use crate::gemini::provider::GeminiClient;
+use crate::models::{LlmProvider, LlmSettings};
+use async_trait::async_trait;
+use openai::OpenAiCompatibleClient;
+use worker::*;
+
+/// Text generation and embeddings, independent of the model vendor.
+#[async_trait(?Send)]
+pub trait LlmClient {
+    async fn generate(&self, prompt: &str) -> std::result::Result<String, ApiError>;
+
+    /// Generates a JSON document conforming to `schema` (standard JSON Schema).
+    async fn generate_json(
+        &self,
+        prompt: &str,
+        schema: &serde_json::Value,
+    ) -> std::result::Result<String, ApiError>;
+
+    async fn embed(&self, text: &str) -> std::result::Result<Vec<f32>, ApiError>;
+
+    /// The model `embed` uses; vectors from different models do not compare.
+    fn embedding_model(&self) -> &str;
+}
+
+/// Reads the deployment-wide backend from the `LLM_PROVIDER`, `LLM_BASE_URL`,
+/// `LLM_MODEL` and `LLM_EMBEDDING_MODEL` vars. Defaults to Gemini.
+pub fn settings_from_env(env: &Env) -> Result<LlmSettings> {
+    let var = |name: &str| env.var(name).ok().map(|v| v.to_string());
+
+    let provider = match var("LLM_PROVIDER").as_deref() {
+        None | Some("gemini") => LlmProvider::Gemini,
+        Some("openai_compatible") => LlmProvider::OpenaiCompatible,
+        Some(other) => return Err(Error::from(format!("Unknown LLM_PROVIDER: {}", other))),
+    };
+
+    Ok(LlmSettings {
+        provider,
+        base_url: var("LLM_BASE_URL"),
+        model: var("LLM_MODEL"),
+        embedding_model: var("LLM_EMBEDDING_MODEL"),
+    })
+}
+
+/// Builds the client for `settings`. Gemini needs the `GEMINI_API_KEY`
+/// secret; OpenAI-compatible servers use `LLM_API_KEY` if it is set.
+pub fn from_settings(env: &Env, settings: &LlmSettings) -> Result<Box<dyn LlmClient>> {
+    match settings.provider {
+        LlmProvider::Gemini => Ok(Box::new(GeminiClient::new(
+            env.secret("GEMINI_API_KEY")?.to_string(),
+            settings.model.clone(),
+            settings.embedding_model.clone(),
+        ))),
+        LlmProvider::OpenaiCompatible => {
+            let base_url = settings.base_url.clone().ok_or_else(|| {
+                Error::from("LLM base_url is required for the openai_compatible provider.")
+            })?;
+            let model = settings.model.clone().ok_or_else(|| {
+                Error::from("LLM model is required for the openai_compatible provider.")
+            })?;
+            Ok(Box::new(OpenAiCompatibleClient::new(
+                base_url,
+                env.secret("LLM_API_KEY").ok().map(|s| s.to_string()),
+                model.clone(),
+                settings.embedding_model.clone().unwrap_or(model),
+            )))
+        }
+    }
+}
@@ -0,0 +1,128 @@
+use super::LlmClient;
+use crate::error::{Api, ApiError};
+use crate::models::*;
+use async_trait::async_trait;
+
+/// `LlmClient` for servers implementing the OpenAI chat-completions and
+/// embeddings endpoints, e.g. `http://localhost:11434/v1` for Ollama.
+pub struct OpenAiCompatibleClient {
+    base_url: String,
+    api_key: Option<String>,
+    model: String,
+    embedding_model: String,
+}
+
+impl OpenAiCompatibleClient {
+    pub fn new(
+        base_url: String,
+        api_key: Option<String>,
+        model: String,
+        embedding_model: String,
+    ) -> Self {
+        Self {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            api_key,
+            model,
+            embedding_model,
+        }
+    }
+
+    async fn post<T: serde::Serialize>(
+        &self,
+        path: &str,
+        body: &T,
+    ) -> Result<reqwest::Response, ApiError> {
+        let client = reqwest::Client::new();
+        let mut request = client
+            .post(format!("{}/{}", self.base_url, path))
+            .json(body);
+        if let Some(api_key) = &self.api_key {
+            request = request.bearer_auth(api_key);
+        }
+
+        let res = request
+            .send()
+            .await
+            .map_err(|e| ApiError::network(Api::OpenAiCompatible, e))?;
+        ApiError::check(Api::OpenAiCompatible, res).await
+    }
+
+    async fn complete(
+        &self,
+        prompt: &str,
+        response_format: Option<serde_json::Value>,
+    ) -> Result<String, ApiError> {
+        let body = ChatCompletionRequest {
+            model: self.model.clone(),
+            messages: vec![ChatMessage {
+                role: "user".to_string(),
+                content: prompt.to_string(),
+            }],
+            response_format,
+        };
+
+        let response_data = self
+            .post("chat/completions", &body)
+            .await?
+            .json::<ChatCompletionResponse>()
+            .await
+            .map_err(|e| {
+                ApiError::parse(Api::OpenAiCompatible, format!("JSON parsing error: {}", e))
+            })?;
+
+        response_data
+            .choices
+            .into_iter()
+            .next()
+            .map(|choice| choice.message.content)
+            .ok_or_else(|| ApiError::parse(Api::OpenAiCompatible, "Response contained no choices"))
+    }
+}
+
+#[async_trait(?Send)]
+impl LlmClient for OpenAiCompatibleClient {
+    async fn generate(&self, prompt: &str) -> Result<String, ApiError> {
+        self.complete(prompt, None).await
+    }
+
+    async fn generate_json(
+        &self,
+        prompt: &str,
+        schema: &serde_json::Value,
+    ) -> Result<String, ApiError> {
+        let response_format = serde_json::json!({
+            "type": "json_schema",
+            "json_schema": { "name": "response", "schema": schema }
+        });
+        self.complete(prompt, Some(response_format)).await
+    }
+
+    async fn embed(&self, text: &str) -> Result<Vec<f32>, ApiError> {
+        let body = OpenAiEmbeddingRequest {
+            model: self.embedding_model.clone(),
+            input: text.to_string(),
+        };
+
+        let response_data = self
+            .post("embeddings", &body)
+            .await?
+            .json::<OpenAiEmbeddingResponse>()
+            .await
+            .map_err(|e| {
+                ApiError::parse(Api::OpenAiCompatible, format!("JSON parsing error: {}", e))
+            })?;
+
+        response_data
+            .data
+            .into_iter()
+            .next()
+            .map(|embedding| embedding.embedding)
+            .ok_or_else(|| {
+                ApiError::parse(Api::OpenAiCompatible, "Response contained no embeddings")
+            })
+    }
+
+    fn embedding_model(&self) -> &str {
+        &self.embedding_model
+    }
+}
@@ -0,0 +1,205 @@
+use std::fmt;
+
+/// One mailbox from an address header, e.g. `"Doe, Jane" <jane@example.com>`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Address {
+    pub name: Option<String>,
+    pub email: String,
+}
+
+impl Address {
+    /// Whether both addresses are the same mailbox; the comparison ignores
+    /// case and display names.
+    pub fn same_mailbox(&self, other: &str) -> bool {
+        self.email.eq_ignore_ascii_case(other.trim())
+    }
+}
+
+impl fmt::Display for Address {
+    /// Formats the mailbox for a header, quoting the display name when it
+    /// contains RFC 5322 specials such as `,`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.name {
+            Some(name) if name.contains(|c: char| "()<>[]:;@\\,.\"".contains(c)) => write!(
+                f,
+                "\"{}\" <{}>",
+                name.replace('\\', "\\\\").replace('"', "\\\""),
+                self.email
+            ),
+            Some(name) => write!(f, "{} <{}>", name, self.email),
+            None => write!(f, "{}", self.email),
+        }
+    }
+}
+
+/// Parses an RFC 5322 address list such as a `To` or `Cc` header. Handles
+/// quoted display names (which may contain commas), comments, and groups,
+/// whose members are returned without the group name. Entries without an
+/// `@` are skipped.
+pub fn parse_address_list(input: &str) -> Vec<Address> {
+    let mut addresses = Vec::new();
+    let mut current = Mailbox::default();
+    let mut chars = input.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                while let Some(c) = chars.next() {
+                    match c {
+                        '\\' => current.phrase.extend(chars.next()),
+                        '"' => break,
+                        _ => current.phrase.push(c),
+                    }
+                }
+            }
+            '(' => {
+                let mut depth = 1;
+                let mut comment = String::new();
+                while let Some(c) = chars.next() {
+                    match c {
+                        '\\' => {
+                            comment.extend(chars.next());
+                            continue;
+                        }
+                        '(' => depth += 1,
+                        ')' => {
+                            depth -= 1;
+                            if depth == 0 {
+                                break;
+                            }
+                        }
+                        _ => {}
+                    }
+                    comment.push(c);
+                }
+                current.comment.get_or_insert(comment);
+            }
+            '<' => {
+                let mut email = String::new();
+                for c in chars.by_ref() {
+                    if c == '>' {
+                        break;
+                    }
+                    email.push(c);
+                }
+                current.angle = Some(email);
+            }
+            // A group's display name ends here; its members follow.
+            ':' if current.angle.is_none() && !current.phrase.contains('@') => {
+                current = Mailbox::default();
+            }
+            ',' | ';' => {
+                addresses.extend(std::mem::take(&mut current).finish());
+            }
+            _ => current.phrase.push(c),
+        }
+    }
+    addresses.extend(current.finish());
+    addresses
+}
+
+/// The pieces of one mailbox collected while scanning.
+#[derive(Default)]
+struct Mailbox {
+    phrase: String,
+    angle: Option<String>,
+    comment: Option<String>,
+}
+
+impl Mailbox {
+    fn finish(self) -> Option<Address> {
+        let collapse = |s: &str| s.split_whitespace().collect::<Vec<_>>().join(" ");
+        let non_empty = |s: String| (!s.is_empty()).then_some(s);
+
+        let (email, name) = match self.angle {
+            Some(email) => (email, non_empty(collapse(&self.phrase))),
+            None => (self.phrase, None),
+        };
+        let email = email.split_whitespace().collect::<String>();
+        if !email.contains('@') {
+            return None;
+        }
+
+        let name = name.or_else(|| self.comment.map(|c| collapse(&c)).and_then(non_empty));
+        Some(Address { name, email })
+    }
+}
+
+/// Formats addresses as a header value.
+pub fn format_address_list(addresses: &[Address]) -> String {
+    addresses
+        .iter()
+        .map(Address::to_string)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn address(name: Option<&str>, email: &str) -> Address {
+        Address {
+            name: name.map(str::to_string),
+            email: email.to_string(),
+        }
+    }
+
+    #[test]
+    fn quoted_display_name_may_contain_commas() {
+        assert_eq!(
+            parse_address_list(r#""Doe, Jane" <jane@x.com>, bob@x.com"#),
+            vec![
+                address(Some("Doe, Jane"), "jane@x.com"),
+                address(None, "bob@x.com")
+            ]
+        );
+    }
+
+    #[test]
+    fn comments_groups_and_junk() {
+        assert_eq!(
+            parse_address_list("jane@x.com (Jane Doe), Team: a@x.com, b@x.com;, undisclosed"),
+            vec![
+                address(Some("Jane Doe"), "jane@x.com"),
+                address(None, "a@x.com"),
+                address(None, "b@x.com"),
+            ]
+        );
+        assert_eq!(
+            parse_address_list(r#""Al \"The Pal\"" <al@x.com>"#),
+            vec![address(Some("Al \"The Pal\""), "al@x.com")]
+        );
+    }
+
+    #[test]
+    fn non_ascii_names_are_kept() {
+        assert_eq!(
+            parse_address_list("山田 太郎 <yamada@example.jp>"),
+            vec![address(Some("山田 太郎"), "yamada@example.jp")]
+        );
+    }
+
+    #[test]
+    fn display_quotes_names_with_specials_and_round_trips() {
+        let addresses = vec![
+            address(Some("Doe, Jane"), "jane@x.com"),
+            address(Some("Bob"), "bob@x.com"),
+            address(None, "c@x.com"),
+        ];
+        let formatted = format_address_list(&addresses);
+        assert_eq!(
+            formatted,
+            r#""Doe, Jane" <jane@x.com>, Bob <bob@x.com>, c@x.com"#
+        );
+        assert_eq!(parse_address_list(&formatted), addresses);
+    }
+
+    #[test]
+    fn same_mailbox_compares_whole_addresses() {
+        let jane = address(Some("Jane"), "Jane@X.com");
+        assert!(jane.same_mailbox("jane@x.com"));
+        assert!(!jane.same_mailbox("ne@x.com"));
+        assert!(!jane.same_mailbox("jane@x.co"));
+    }
+}
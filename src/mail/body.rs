@@ -0,0 +1,149 @@
+use super::html;
+use crate::models::MessagePart;
+use base64::{
+    alphabet,
+    engine::{DecodePaddingMode, GeneralPurpose, GeneralPurposeConfig},
+    Engine as _,
+};
+use encoding_rs::{Encoding, UTF_8};
+
+/// Gmail sends part bodies as base64url, normally padded; accept both.
+const BASE64_URL: GeneralPurpose = GeneralPurpose::new(
+    &alphabet::URL_SAFE,
+    GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent),
+);
+
+/// Returns the readable text of a message: its `text/plain` part, or failing
+/// that its `text/html` part converted to text. Attachments are ignored.
+pub fn extract_text(payload: &MessagePart) -> Option<String> {
+    if let Some(text) = find_part(payload, "text/plain").and_then(decode_part) {
+        return Some(text);
+    }
+    find_part(payload, "text/html")
+        .and_then(decode_part)
+        .map(|markup| html::to_text(&markup))
+}
+
+/// Depth-first search for the first inline part of the given MIME type.
+fn find_part<'a>(payload: &'a MessagePart, mime_type: &str) -> Option<&'a MessagePart> {
+    if payload.mime_type.eq_ignore_ascii_case(mime_type)
+        && payload.filename.is_empty()
+        && payload.body.data.is_some()
+    {
+        return Some(payload);
+    }
+
+    payload
+        .parts
+        .iter()
+        .flatten()
+        .find_map(|part| find_part(part, mime_type))
+}
+
+/// Decodes the part's body using the charset from its `Content-Type` header,
+/// e.g. ISO-2022-JP, Shift_JIS or EUC-JP. Unknown or missing charsets are
+/// read as UTF-8.
+fn decode_part(part: &MessagePart) -> Option<String> {
+    let data = part.body.data.as_ref()?;
+    let data: String = data.chars().filter(|c| !c.is_whitespace()).collect();
+    let bytes = BASE64_URL.decode(data).ok()?;
+
+    let encoding = charset(part)
+        .and_then(|label| Encoding::for_label(label.as_bytes()))
+        .unwrap_or(UTF_8);
+    let (text, _, _) = encoding.decode(&bytes);
+    Some(text.into_owned())
+}
+
+/// The `charset` parameter of the part's `Content-Type` header, if any.
+fn charset(part: &MessagePart) -> Option<String> {
+    let content_type = part
+        .headers
+        .iter()
+        .find(|h| h.name.eq_ignore_ascii_case("Content-Type"))?;
+
+    content_type.value.split(';').skip(1).find_map(|param| {
+        let (name, value) = param.split_once('=')?;
+        name.trim()
+            .eq_ignore_ascii_case("charset")
+            .then(|| value.trim().trim_matches('"').to_string())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{MessagePartBody, MessagePartHeaders};
+
+    fn part(mime_type: &str, filename: &str, data: &[u8], parts: Vec<MessagePart>) -> MessagePart {
+        MessagePart {
+            part_id: String::new(),
+            mime_type: mime_type.to_string(),
+            filename: filename.to_string(),
+            headers: Vec::new(),
+            body: MessagePartBody {
+                size: data.len() as u32,
+                data: (!data.is_empty()).then(|| BASE64_URL.encode(data)),
+            },
+            parts: (!parts.is_empty()).then_some(parts),
+        }
+    }
+
+    #[test]
+    fn prefers_the_plain_text_part() {
+        let payload = part(
+            "multipart/alternative",
+            "",
+            b"",
+            vec![
+                part("text/plain", "", b"Plain version", Vec::new()),
+                part("text/html", "", b"<p>HTML version</p>", Vec::new()),
+            ],
+        );
+
+        assert_eq!(extract_text(&payload).as_deref(), Some("Plain version"));
+    }
+
+    #[test]
+    fn falls_back_to_html_converted_to_text() {
+        let payload = part(
+            "multipart/mixed",
+            "",
+            b"",
+            vec![
+                part(
+                    "multipart/related",
+                    "",
+                    b"",
+                    vec![part(
+                        "text/html",
+                        "",
+                        b"<p>Hi,</p><ul><li>one</li><li>two</li></ul>",
+                        Vec::new(),
+                    )],
+                ),
+                part("text/plain", "notes.txt", b"attached notes", Vec::new()),
+            ],
+        );
+
+        assert_eq!(
+            extract_text(&payload).as_deref(),
+            Some("Hi,\n\n- one\n- two")
+        );
+    }
+
+    #[test]
+    fn decodes_the_declared_charset() {
+        let (bytes, _, _) = encoding_rs::SHIFT_JIS.encode("お世話になっております。");
+        let mut payload = part("text/plain", "", &bytes, Vec::new());
+        payload.headers.push(MessagePartHeaders {
+            name: "Content-Type".to_string(),
+            value: "text/plain; charset=\"Shift_JIS\"".to_string(),
+        });
+
+        assert_eq!(
+            extract_text(&payload).as_deref(),
+            Some("お世話になっております。")
+        );
+    }
+}
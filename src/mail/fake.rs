@@ -0,0 +1,252 @@
+use super::MailProvider;
+use crate::error::{Api, ApiError};
+use crate::models::{
+    BotLabel, DraftReply, Message, MessageId, MessageListResponse, MessagePart, MessagePartBody,
+    MessagePartHeaders, SendAs, WatchResponse,
+};
+use async_trait::async_trait;
+use base64::{engine::general_purpose::URL_SAFE, Engine as _};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+struct FakeMessage {
+    message: Message,
+    thread_id: String,
+    label_ids: Vec<String>,
+}
+
+impl FakeMessage {
+    /// The message as the API returns it, with its current labels.
+    fn snapshot(&self) -> Message {
+        Message {
+            label_ids: self.label_ids.clone(),
+            ..self.message.clone()
+        }
+    }
+}
+
+/// In-memory mailbox for exercising the pipeline without the Gmail API.
+/// Messages are seeded with `add_message`; drafts and label changes are
+/// recorded and can be inspected afterwards. Clones share the mailbox.
+#[derive(Default, Clone)]
+pub struct FakeMailProvider {
+    messages: Rc<RefCell<Vec<FakeMessage>>>,
+    drafts: Rc<RefCell<Vec<DraftReply>>>,
+    send_as: Rc<RefCell<Vec<SendAs>>>,
+    /// Errors `get_message` returns instead of the message.
+    failures: Rc<RefCell<HashMap<String, ApiError>>>,
+}
+
+impl FakeMailProvider {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds an unread `text/plain` message with the given headers and body.
+    /// Messages count as received in the order they are added.
+    pub fn add_message(&self, id: &str, thread_id: &str, headers: &[(&str, &str)], body: &str) {
+        let encoded_body = URL_SAFE.encode(body);
+        let received = self.messages.borrow().len();
+        let message = Message {
+            id: id.to_string(),
+            snippet: body.chars().take(100).collect(),
+            payload: MessagePart {
+                part_id: String::new(),
+                mime_type: "text/plain".to_string(),
+                filename: String::new(),
+                headers: headers
+                    .iter()
+                    .map(|(name, value)| MessagePartHeaders {
+                        name: name.to_string(),
+                        value: value.to_string(),
+                    })
+                    .collect(),
+                body: MessagePartBody {
+                    size: body.len() as u32,
+                    data: Some(encoded_body),
+                },
+                parts: None,
+            },
+            label_ids: Vec::new(),
+            internal_date: Some(received.to_string()),
+        };
+
+        self.messages.borrow_mut().push(FakeMessage {
+            message,
+            thread_id: thread_id.to_string(),
+            label_ids: vec!["INBOX".to_string(), "UNREAD".to_string()],
+        });
+    }
+
+    /// Adds a send-as address; the first one added is the primary address.
+    pub fn add_send_as(&self, email: &str, display_name: Option<&str>) {
+        let mut send_as = self.send_as.borrow_mut();
+        let is_primary = send_as.is_empty();
+        send_as.push(SendAs {
+            send_as_email: email.to_string(),
+            display_name: display_name.map(str::to_string),
+            signature: None,
+            is_primary,
+            is_default: is_primary,
+        });
+    }
+
+    /// Makes `get_message` fail with `error` for `message_id`, which still
+    /// shows up in listings.
+    pub fn fail_message(&self, message_id: &str, error: ApiError) {
+        self.failures
+            .borrow_mut()
+            .insert(message_id.to_string(), error);
+    }
+
+    pub fn drafts(&self) -> Vec<DraftReply> {
+        self.drafts.borrow().clone()
+    }
+
+    pub fn labels(&self, message_id: &str) -> Vec<String> {
+        self.messages
+            .borrow()
+            .iter()
+            .find(|m| m.message.id == message_id)
+            .map(|m| m.label_ids.clone())
+            .unwrap_or_default()
+    }
+
+    fn not_found(message_id: &str) -> ApiError {
+        ApiError::http(
+            Api::Gmail,
+            404,
+            &format!("Requested entity {} was not found.", message_id),
+        )
+    }
+}
+
+#[async_trait(?Send)]
+impl MailProvider for FakeMailProvider {
+    /// Page tokens are the offset into the unread list. Label IDs are the
+    /// label names (see `label_id`).
+    async fn list_unread(
+        &self,
+        page_token: Option<&str>,
+        max_results: u32,
+    ) -> Result<MessageListResponse, ApiError> {
+        let unread: Vec<MessageId> = self
+            .messages
+            .borrow()
+            .iter()
+            .filter(|m| m.label_ids.iter().any(|l| l == "UNREAD"))
+            .filter(|m| {
+                !BotLabel::ALL
+                    .iter()
+                    .any(|label| label.is_final() && m.label_ids.iter().any(|l| l == label.name()))
+            })
+            .map(|m| MessageId {
+                id: m.message.id.clone(),
+                thread_id: m.thread_id.clone(),
+            })
+            .collect();
+
+        let start = page_token
+            .and_then(|token| token.parse::<usize>().ok())
+            .unwrap_or(0)
+            .min(unread.len());
+        let end = (start + max_results as usize).min(unread.len());
+
+        Ok(MessageListResponse {
+            messages: Some(unread[start..end].to_vec()),
+            next_page_token: (end < unread.len()).then(|| end.to_string()),
+        })
+    }
+
+    async fn get_message(&self, message_id: &str) -> Result<Message, ApiError> {
+        if let Some(error) = self.failures.borrow().get(message_id) {
+            return Err(error.clone());
+        }
+        self.messages
+            .borrow()
+            .iter()
+            .find(|m| m.message.id == message_id)
+            .map(FakeMessage::snapshot)
+            .ok_or_else(|| Self::not_found(message_id))
+    }
+
+    async fn get_thread(&self, thread_id: &str) -> Result<Vec<Message>, ApiError> {
+        let messages: Vec<Message> = self
+            .messages
+            .borrow()
+            .iter()
+            .filter(|m| m.thread_id == thread_id)
+            .map(FakeMessage::snapshot)
+            .collect();
+        if messages.is_empty() {
+            return Err(Self::not_found(thread_id));
+        }
+        Ok(messages)
+    }
+
+    /// Draft IDs are `draft-<n>`, numbered from 1.
+    async fn create_draft(&self, draft: &DraftReply) -> Result<String, ApiError> {
+        let mut drafts = self.drafts.borrow_mut();
+        drafts.push(draft.clone());
+        Ok(format!("draft-{}", drafts.len()))
+    }
+
+    async fn list_send_as(&self) -> Result<Vec<SendAs>, ApiError> {
+        Ok(self.send_as.borrow().clone())
+    }
+
+    async fn label_id(&self, name: &str) -> Result<String, ApiError> {
+        Ok(name.to_string())
+    }
+
+    async fn modify_labels(
+        &self,
+        message_id: &str,
+        add_label_ids: &[&str],
+        remove_label_ids: &[&str],
+    ) -> Result<(), ApiError> {
+        let mut messages = self.messages.borrow_mut();
+        let message = messages
+            .iter_mut()
+            .find(|m| m.message.id == message_id)
+            .ok_or_else(|| Self::not_found(message_id))?;
+
+        message
+            .label_ids
+            .retain(|label| !remove_label_ids.contains(&label.as_str()));
+        for label in add_label_ids {
+            if !message.label_ids.iter().any(|l| l == label) {
+                message.label_ids.push(label.to_string());
+            }
+        }
+        Ok(())
+    }
+
+    /// History IDs are message counts: the messages added after the first
+    /// `start_history_id` ones that are still unread.
+    async fn list_history(
+        &self,
+        start_history_id: &str,
+    ) -> Result<(Vec<MessageId>, String), ApiError> {
+        let start = start_history_id.parse::<usize>().unwrap_or(0);
+        let messages = self.messages.borrow();
+        let added = messages
+            .iter()
+            .skip(start)
+            .filter(|m| m.label_ids.iter().any(|l| l == "UNREAD"))
+            .map(|m| MessageId {
+                id: m.message.id.clone(),
+                thread_id: m.thread_id.clone(),
+            })
+            .collect();
+        Ok((added, messages.len().to_string()))
+    }
+
+    async fn watch(&self, _topic_name: &str) -> Result<WatchResponse, ApiError> {
+        Ok(WatchResponse {
+            history_id: self.messages.borrow().len().to_string(),
+            expiration: "0".to_string(),
+        })
+    }
+}
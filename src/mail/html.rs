@@ -0,0 +1,497 @@
+//! A small HTML-to-text converter for email bodies. It is not a full HTML
+//! parser: it only needs to turn the markup mail clients and newsletters send
+//! into something a model (or a human) can read.
+
+/// Elements whose content is never shown.
+const HIDDEN_ELEMENTS: [&str; 6] = ["script", "style", "head", "title", "noscript", "template"];
+
+/// Elements that start and end on their own line.
+const BLOCK_ELEMENTS: [&str; 21] = [
+    "p",
+    "div",
+    "section",
+    "article",
+    "header",
+    "footer",
+    "main",
+    "nav",
+    "aside",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "table",
+    "tr",
+    "ul",
+    "ol",
+    "blockquote",
+    "pre",
+];
+
+struct Tag {
+    name: String,
+    closing: bool,
+    attributes: Vec<(String, String)>,
+}
+
+impl Tag {
+    fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// An open `<ul>` or `<ol>`; `next` is the number of the next `<ol>` item.
+struct List {
+    ordered: bool,
+    next: u32,
+}
+
+/// An open `<a>`: its target and where its text starts in the output.
+struct Link {
+    href: Option<String>,
+    text_start: usize,
+}
+
+#[derive(Default)]
+struct Converter {
+    out: String,
+    lists: Vec<List>,
+    links: Vec<Link>,
+    /// Whether the current table row already has a cell.
+    row_has_cell: bool,
+    pre_depth: usize,
+}
+
+/// Converts an HTML document or fragment to plain text. Links keep their
+/// target as `text (url)`, list items become `- ` or `1. ` lines and table
+/// cells are separated by ` | `. Scripts, styles and images without alt
+/// text, which includes tracking pixels, are dropped.
+pub fn to_text(html: &str) -> String {
+    let mut converter = Converter::default();
+    let mut rest = html;
+
+    while let Some(start) = rest.find('<') {
+        converter.text(&rest[..start]);
+        rest = &rest[start..];
+
+        if let Some(comment) = rest.strip_prefix("<!--") {
+            rest = comment.find("-->").map_or("", |end| &comment[end + 3..]);
+            continue;
+        }
+
+        let Some((tag, after)) = parse_tag(rest) else {
+            // A stray `<` that does not start a tag.
+            converter.text("<");
+            rest = &rest[1..];
+            continue;
+        };
+        rest = after;
+
+        if !tag.closing && HIDDEN_ELEMENTS.contains(&tag.name.as_str()) {
+            rest = skip_element(rest, &tag.name);
+            continue;
+        }
+        converter.tag(&tag);
+    }
+    converter.text(rest);
+
+    converter.finish()
+}
+
+impl Converter {
+    fn text(&mut self, raw: &str) {
+        let decoded = decode_entities(raw);
+        if self.pre_depth > 0 {
+            self.out.push_str(&decoded);
+            return;
+        }
+
+        for c in decoded.chars() {
+            if c.is_whitespace() {
+                if !self.out.is_empty() && !self.out.ends_with([' ', '\n']) {
+                    self.out.push(' ');
+                }
+            } else {
+                self.out.push(c);
+            }
+        }
+    }
+
+    fn newline(&mut self) {
+        while self.out.ends_with(' ') {
+            self.out.pop();
+        }
+        if !self.out.is_empty() && !self.out.ends_with('\n') {
+            self.out.push('\n');
+        }
+    }
+
+    fn blank_line(&mut self) {
+        self.newline();
+        if !self.out.is_empty() && !self.out.ends_with("\n\n") {
+            self.out.push('\n');
+        }
+    }
+
+    fn tag(&mut self, tag: &Tag) {
+        let name = tag.name.as_str();
+        match (name, tag.closing) {
+            ("br", _) => {
+                while self.out.ends_with(' ') {
+                    self.out.pop();
+                }
+                self.out.push('\n');
+            }
+            ("hr", _) => {
+                self.newline();
+                self.out.push_str("---\n");
+            }
+            ("ul" | "ol", false) => {
+                self.newline();
+                self.lists.push(List {
+                    ordered: name == "ol",
+                    next: tag
+                        .attribute("start")
+                        .and_then(|start| start.parse().ok())
+                        .unwrap_or(1),
+                });
+            }
+            ("ul" | "ol", true) => {
+                self.lists.pop();
+                self.newline();
+            }
+            ("li", false) => {
+                self.newline();
+                let depth = self.lists.len().saturating_sub(1);
+                self.out.push_str(&"  ".repeat(depth));
+                match self.lists.last_mut() {
+                    Some(list) if list.ordered => {
+                        self.out.push_str(&format!("{}. ", list.next));
+                        list.next += 1;
+                    }
+                    _ => self.out.push_str("- "),
+                }
+            }
+            ("li", true) => self.newline(),
+            ("tr", false) => {
+                self.newline();
+                self.row_has_cell = false;
+            }
+            ("td" | "th", false) => {
+                if self.row_has_cell {
+                    while self.out.ends_with(' ') {
+                        self.out.pop();
+                    }
+                    self.out.push_str(" | ");
+                }
+                self.row_has_cell = true;
+            }
+            ("a", false) => self.links.push(Link {
+                href: tag.attribute("href").map(str::to_string),
+                text_start: self.out.len(),
+            }),
+            ("a", true) => {
+                if let Some(link) = self.links.pop() {
+                    self.close_link(link);
+                }
+            }
+            ("img", _) if !is_tracking_pixel(tag) => {
+                if let Some(alt) = tag.attribute("alt").filter(|alt| !alt.trim().is_empty()) {
+                    self.text(&format!("[{}]", alt.trim()));
+                }
+            }
+            ("pre", false) => {
+                self.blank_line();
+                self.pre_depth += 1;
+            }
+            ("pre", true) => {
+                self.pre_depth = self.pre_depth.saturating_sub(1);
+                self.blank_line();
+            }
+            ("p" | "blockquote" | "table" | "h1" | "h2" | "h3" | "h4" | "h5" | "h6", _) => {
+                self.blank_line()
+            }
+            _ if BLOCK_ELEMENTS.contains(&name) => self.newline(),
+            _ => {}
+        }
+    }
+
+    /// Appends the link target after its text, unless the text already is
+    /// the target or the target is not worth showing (anchors, javascript).
+    fn close_link(&mut self, link: Link) {
+        let Some(href) = link.href else { return };
+        let href = href.trim();
+        let shown = ["http://", "https://", "mailto:"]
+            .iter()
+            .any(|scheme| href.to_ascii_lowercase().starts_with(scheme));
+        if !shown {
+            return;
+        }
+
+        let text = self.out.get(link.text_start..).unwrap_or_default().trim();
+        let target = href.strip_prefix("mailto:").unwrap_or(href);
+        if text == target || text == href {
+            return;
+        }
+        if text.is_empty() {
+            self.text(href);
+        } else {
+            self.out.push_str(&format!(" ({})", href));
+        }
+    }
+
+    fn finish(self) -> String {
+        let mut text = String::new();
+        let mut blank_lines = 0;
+        for line in self.out.lines().map(str::trim_end) {
+            if line.trim().is_empty() {
+                blank_lines += 1;
+                if blank_lines > 1 {
+                    continue;
+                }
+            } else {
+                blank_lines = 0;
+            }
+            text.push_str(line);
+            text.push('\n');
+        }
+        text.trim().to_string()
+    }
+}
+
+/// Images that are only there to report that the mail was opened: 1x1 (or
+/// 0x0) images and hidden ones.
+fn is_tracking_pixel(tag: &Tag) -> bool {
+    let tiny = |value: Option<&str>| {
+        value
+            .map(|v| v.trim().trim_end_matches("px"))
+            .and_then(|v| v.parse::<f32>().ok())
+            .is_some_and(|v| v <= 1.0)
+    };
+    let style = tag
+        .attribute("style")
+        .unwrap_or_default()
+        .to_ascii_lowercase()
+        .replace(' ', "");
+
+    tiny(tag.attribute("width"))
+        || tiny(tag.attribute("height"))
+        || style.contains("display:none")
+        || style.contains("visibility:hidden")
+}
+
+/// Parses the tag at the start of `input` (which begins with `<`), returning
+/// it together with the input after its closing `>`.
+fn parse_tag(input: &str) -> Option<(Tag, &str)> {
+    let inner = &input[1..];
+    let (closing, inner) = match inner.strip_prefix('/') {
+        Some(rest) => (true, rest),
+        None => (false, inner),
+    };
+    // Declarations and processing instructions (`<!DOCTYPE>`, `<?xml?>`)
+    // are parsed like tags and then ignored.
+    let inner = inner.trim_start_matches(['!', '?']);
+    if !inner.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        return None;
+    }
+
+    let mut quote = None;
+    let end = inner.char_indices().find_map(|(i, c)| match (quote, c) {
+        (None, '"' | '\'') => {
+            quote = Some(c);
+            None
+        }
+        (Some(q), _) if c == q => {
+            quote = None;
+            None
+        }
+        (None, '>') => Some(i),
+        _ => None,
+    })?;
+
+    let body = &inner[..end];
+    let name_end = body
+        .find(|c: char| c.is_whitespace() || c == '/')
+        .unwrap_or(body.len());
+    let tag = Tag {
+        name: body[..name_end].to_ascii_lowercase(),
+        closing,
+        attributes: parse_attributes(&body[name_end..]),
+    };
+    Some((tag, &inner[end + 1..]))
+}
+
+fn parse_attributes(input: &str) -> Vec<(String, String)> {
+    let mut attributes = Vec::new();
+    let mut rest = input;
+
+    loop {
+        rest = rest.trim_start_matches(|c: char| c.is_whitespace() || c == '/');
+        let name_end = rest
+            .find(|c: char| c.is_whitespace() || c == '=' || c == '/')
+            .unwrap_or(rest.len());
+        if name_end == 0 {
+            return attributes;
+        }
+        let name = rest[..name_end].to_ascii_lowercase();
+        rest = rest[name_end..].trim_start();
+
+        let value = match rest.strip_prefix('=') {
+            Some(after) => {
+                let after = after.trim_start();
+                let (value, remaining) = match after.chars().next() {
+                    Some(q @ ('"' | '\'')) => {
+                        let quoted = &after[1..];
+                        let end = quoted.find(q).unwrap_or(quoted.len());
+                        (&quoted[..end], quoted.get(end + 1..).unwrap_or(""))
+                    }
+                    _ => {
+                        let end = after.find(char::is_whitespace).unwrap_or(after.len());
+                        (&after[..end], &after[end..])
+                    }
+                };
+                rest = remaining;
+                decode_entities(value)
+            }
+            None => String::new(),
+        };
+        attributes.push((name, value));
+    }
+}
+
+/// Returns the input after the closing tag of the element `name`, or nothing
+/// if it is never closed.
+fn skip_element<'a>(input: &'a str, name: &str) -> &'a str {
+    let closing = format!("</{}", name);
+    let lower = input.to_ascii_lowercase();
+    match lower.find(&closing) {
+        Some(start) => {
+            let after = &input[start..];
+            after.find('>').map_or("", |end| &after[end + 1..])
+        }
+        None => "",
+    }
+}
+
+/// Decodes numeric character references and the named entities that
+/// commonly appear in mail. Unknown entities are kept as they are.
+fn decode_entities(input: &str) -> String {
+    if !input.contains('&') {
+        return input.to_string();
+    }
+
+    let mut out = String::with_capacity(input.len());
+    let mut rest = input;
+    while let Some(start) = rest.find('&') {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let decoded = rest[1..]
+            .find(';')
+            .filter(|&end| end <= 10)
+            .and_then(|end| decode_entity(&rest[1..end + 1]).map(|c| (c, end + 2)));
+        match decoded {
+            Some((c, len)) => {
+                out.push(c);
+                rest = &rest[len..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+fn decode_entity(entity: &str) -> Option<char> {
+    if let Some(number) = entity.strip_prefix('#') {
+        let code = match number.strip_prefix(['x', 'X']) {
+            Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+            None => number.parse().ok()?,
+        };
+        return char::from_u32(code);
+    }
+
+    Some(match entity {
+        "amp" => '&',
+        "lt" => '<',
+        "gt" => '>',
+        "quot" => '"',
+        "apos" => '\'',
+        "nbsp" => ' ',
+        "copy" => '©',
+        "reg" => '®',
+        "trade" => '™',
+        "yen" => '¥',
+        "euro" => '€',
+        "hellip" => '…',
+        "mdash" => '—',
+        "ndash" => '–',
+        "lsquo" => '‘',
+        "rsquo" => '’',
+        "ldquo" => '“',
+        "rdquo" => '”',
+        "laquo" => '«',
+        "raquo" => '»',
+        "middot" => '·',
+        "bull" => '•',
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn links_keep_their_target() {
+        let html = r##"<p>See <a href="https://example.com/terms">our terms</a>, mail
+            <a href="mailto:info@example.com">info@example.com</a> or
+            <a href="https://example.com">https://example.com</a>.
+            <a href="#top">Back to top</a></p>"##;
+
+        assert_eq!(
+            to_text(html),
+            "See our terms (https://example.com/terms), mail info@example.com or \
+             https://example.com. Back to top"
+        );
+    }
+
+    #[test]
+    fn lists_become_bulleted_and_numbered_lines() {
+        let html = "<p>Agenda:</p><ol start=\"3\"><li>Budget</li><li>Hiring\
+            <ul><li>Engineers</li><li>Sales</li></ul></li></ol><ul><li>AOB</li></ul>";
+
+        assert_eq!(
+            to_text(html),
+            "Agenda:\n\n3. Budget\n4. Hiring\n  - Engineers\n  - Sales\n- AOB"
+        );
+    }
+
+    #[test]
+    fn table_cells_are_separated_by_pipes() {
+        let html = "<table><tr><th>Item</th><th>Price</th></tr>\
+            <tr><td>Coffee</td><td>&yen;450</td></tr></table><p>Thanks</p>";
+
+        assert_eq!(to_text(html), "Item | Price\nCoffee | ¥450\n\nThanks");
+    }
+
+    #[test]
+    fn tracking_pixels_and_hidden_content_are_dropped() {
+        let html = r#"<html><head><title>News</title><style>p { color: red; }</style></head>
+            <body><p>Hello&nbsp;there</p>
+            <img src="https://t.example.com/open.gif" width="1" height="1" alt="pixel">
+            <img src="https://t.example.com/o.gif" style="display: none" alt="x">
+            <img src="logo.png" alt="Example Inc.">
+            <script>track();</script><!-- <p>comment</p> --></body></html>"#;
+
+        assert_eq!(to_text(html), "Hello there\n\n[Example Inc.]");
+    }
+}
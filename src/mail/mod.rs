@@ -0,0 +1,59 @@
+pub mod address;
+pub mod body;
+#[cfg(test)]
+pub mod fake;
+pub mod html;
+pub mod quote;
+pub mod recipients;
+pub mod signature;
+pub mod subject;
+pub mod thread;
+
+use crate::error::ApiError;
+use crate::models::{DraftReply, Message, MessageId, MessageListResponse, SendAs, WatchResponse};
+use async_trait::async_trait;
+
+/// The mailbox operations the pipeline needs. `gmail::provider::GmailProvider`
+/// talks to the Gmail API; `fake::FakeMailProvider` keeps everything in memory.
+#[async_trait(?Send)]
+pub trait MailProvider {
+    /// Lists one page of the messages the pipeline still has to look at:
+    /// unread, and without a final `BotLabel`. `page_token` is the
+    /// `next_page_token` of the previous page.
+    async fn list_unread(
+        &self,
+        page_token: Option<&str>,
+        max_results: u32,
+    ) -> Result<MessageListResponse, ApiError>;
+
+    async fn get_message(&self, message_id: &str) -> Result<Message, ApiError>;
+
+    /// The messages of a thread, in the order the provider returns them.
+    async fn get_thread(&self, thread_id: &str) -> Result<Vec<Message>, ApiError>;
+
+    /// Creates the draft and returns its ID.
+    async fn create_draft(&self, draft: &DraftReply) -> Result<String, ApiError>;
+
+    /// The addresses the user can send as, including the primary one.
+    async fn list_send_as(&self) -> Result<Vec<SendAs>, ApiError>;
+
+    /// Returns the ID of the user label called `name`, creating it if needed.
+    async fn label_id(&self, name: &str) -> Result<String, ApiError>;
+
+    async fn modify_labels(
+        &self,
+        message_id: &str,
+        add_label_ids: &[&str],
+        remove_label_ids: &[&str],
+    ) -> Result<(), ApiError>;
+
+    /// The unread messages added since `start_history_id`, with the
+    /// mailbox's current history ID to continue from next time.
+    async fn list_history(
+        &self,
+        start_history_id: &str,
+    ) -> Result<(Vec<MessageId>, String), ApiError>;
+
+    /// Registers push notifications for new mail on the Pub/Sub topic.
+    async fn watch(&self, topic_name: &str) -> Result<WatchResponse, ApiError>;
+}
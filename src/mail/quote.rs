@@ -0,0 +1,158 @@
+use super::signature::Signature;
+
+/// The message a reply is written to, as it is quoted below the reply.
+pub struct QuotedOriginal {
+    /// The original `Date` header, if it had one.
+    pub date: Option<String>,
+    /// The original `From` header.
+    pub sender: String,
+    /// The original body as plain text.
+    pub text: String,
+}
+
+impl QuotedOriginal {
+    /// The line introducing the quote, e.g.
+    /// `On Tue, Oct 14, 2025 at 9:12 AM, Jane <jane@example.com> wrote:`.
+    pub fn attribution(&self) -> String {
+        match &self.date {
+            Some(date) => format!("On {}, {} wrote:", format_date(date), self.sender),
+            None => format!("{} wrote:", self.sender),
+        }
+    }
+
+    /// `reply` and `signature` followed by the original with every line
+    /// prefixed by `> `.
+    pub fn plain_reply(&self, reply: &str, signature: &Signature) -> String {
+        let quoted = self
+            .text
+            .lines()
+            .map(|line| {
+                if line.is_empty() {
+                    ">".to_string()
+                } else {
+                    format!("> {}", line)
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        format!(
+            "{}\n\n{}\n\n{}\n{}\n",
+            reply.trim_end(),
+            signature.text,
+            self.attribution(),
+            quoted
+        )
+    }
+
+    /// `reply` as HTML and `signature` followed by the original in a
+    /// `blockquote`, using the markup Gmail itself uses so that clients
+    /// collapse the quote.
+    pub fn html_reply(&self, reply: &str, signature: &Signature) -> String {
+        format!(
+            "<div dir=\"ltr\">{reply}<br><br>{signature}</div><br>\
+             <div class=\"gmail_quote\">\
+             <div dir=\"ltr\" class=\"gmail_attr\">{attribution}<br></div>\
+             <blockquote class=\"gmail_quote\" style=\"margin:0px 0px 0px 0.8ex;border-left:1px solid rgb(204,204,204);padding-left:1ex\">\
+             {quoted}\
+             </blockquote></div>",
+            reply = text_to_html(reply.trim_end()),
+            signature = signature.html,
+            attribution = escape_html(&self.attribution()),
+            quoted = text_to_html(&self.text)
+        )
+    }
+}
+
+/// Formats an RFC 2822 date the way Gmail's attribution line does; other
+/// formats are shown unchanged.
+fn format_date(date: &str) -> String {
+    chrono::DateTime::parse_from_rfc2822(date)
+        .map(|date| date.format("%a, %b %-d, %Y at %-I:%M %p").to_string())
+        .unwrap_or_else(|_| date.to_string())
+}
+
+/// Escapes text for HTML and keeps its line breaks.
+pub fn text_to_html(text: &str) -> String {
+    escape_html(text)
+        .replace("\r\n", "\n")
+        .replace('\n', "<br>")
+}
+
+pub fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn original(date: Option<&str>) -> QuotedOriginal {
+        QuotedOriginal {
+            date: date.map(str::to_string),
+            sender: "Jane Doe <jane@example.com>".to_string(),
+            text: "Can we meet on Friday?\n\nThanks,\nJane".to_string(),
+        }
+    }
+
+    fn signature() -> Signature {
+        Signature {
+            text: "-- \nMe".to_string(),
+            html: "<div class=\"gmail_signature\">Me</div>".to_string(),
+        }
+    }
+
+    #[test]
+    fn attribution_formats_rfc_2822_dates_like_gmail() {
+        assert_eq!(
+            original(Some("Tue, 14 Oct 2025 21:05:00 +0900")).attribution(),
+            "On Tue, Oct 14, 2025 at 9:05 PM, Jane Doe <jane@example.com> wrote:"
+        );
+        assert_eq!(
+            original(Some("yesterday")).attribution(),
+            "On yesterday, Jane Doe <jane@example.com> wrote:"
+        );
+        assert_eq!(
+            original(None).attribution(),
+            "Jane Doe <jane@example.com> wrote:"
+        );
+    }
+
+    #[test]
+    fn plain_reply_quotes_every_line_below_the_signature() {
+        let reply = original(None).plain_reply("Friday works.\n\n", &signature());
+
+        assert_eq!(
+            reply,
+            "Friday works.\n\n-- \nMe\n\nJane Doe <jane@example.com> wrote:\n\
+             > Can we meet on Friday?\n>\n> Thanks,\n> Jane\n"
+        );
+    }
+
+    #[test]
+    fn html_reply_escapes_text_and_quotes_in_a_gmail_blockquote() {
+        let reply = original(Some("Tue, 14 Oct 2025 09:05:00 +0000"))
+            .html_reply("Fine by me & <team>.\nSee you", &signature());
+
+        assert!(reply.starts_with(
+            "<div dir=\"ltr\">Fine by me &amp; &lt;team&gt;.<br>See you<br><br>\
+             <div class=\"gmail_signature\">Me</div></div><br><div class=\"gmail_quote\">"
+        ));
+        assert!(reply.contains(
+            "<div dir=\"ltr\" class=\"gmail_attr\">On Tue, Oct 14, 2025 at 9:05 AM, \
+             Jane Doe &lt;jane@example.com&gt; wrote:<br></div>"
+        ));
+        assert!(reply.ends_with("Can we meet on Friday?<br><br>Thanks,<br>Jane</blockquote></div>"));
+    }
+
+    #[test]
+    fn escape_html_escapes_markup_and_quotes() {
+        assert_eq!(
+            escape_html(r#"<a href="x">Tom & Jerry</a>"#),
+            "&lt;a href=&quot;x&quot;&gt;Tom &amp; Jerry&lt;/a&gt;"
+        );
+        assert_eq!(text_to_html("one\r\ntwo\nthree"), "one<br>two<br>three");
+    }
+}
@@ -0,0 +1,157 @@
+use super::address::{parse_address_list, Address};
+use crate::models::{MessagePartHeaders, RecipientPolicy};
+
+/// Who a reply-all to a message goes to.
+#[derive(Debug, Clone, Default)]
+pub struct ReplyRecipients {
+    pub to: Vec<Address>,
+    pub cc: Vec<Address>,
+}
+
+impl ReplyRecipients {
+    fn len(&self) -> usize {
+        self.to.len() + self.cc.len()
+    }
+}
+
+/// Recipients for a reply to a message with the given headers. For a
+/// reply-all, `Reply-To` (or `From` when there is none) and the original `To`
+/// go in `to` and the original `Cc` in `cc`; `policy` may narrow that to the
+/// sender alone. Any of `own_addresses` (the user's address and send-as
+/// aliases) is removed, and an address appears only once across both lists.
+/// If the sender is one of `own_addresses`, e.g. a follow-up to the user's
+/// own mail, a sender-only reply goes to the reply-all recipients instead.
+pub fn compute_reply_recipients(
+    headers: &[MessagePartHeaders],
+    own_addresses: &[String],
+    policy: RecipientPolicy,
+) -> ReplyRecipients {
+    let header = |name: &str| {
+        headers
+            .iter()
+            .find(|h| h.name.eq_ignore_ascii_case(name))
+            .map_or("", |h| h.value.as_str())
+    };
+
+    let reply_to = parse_address_list(header("Reply-To"));
+    let sender = if reply_to.is_empty() {
+        parse_address_list(header("From"))
+    } else {
+        reply_to
+    };
+
+    let mut seen: Vec<Address> = Vec::new();
+    let mut keep = |address: &Address| {
+        let own = own_addresses.iter().any(|own| address.same_mailbox(own));
+        let duplicate = seen.iter().any(|s| address.same_mailbox(&s.email));
+        if own || duplicate {
+            return false;
+        }
+        seen.push(address.clone());
+        true
+    };
+
+    let sender: Vec<Address> = sender.into_iter().filter(|a| keep(a)).collect();
+    let others: Vec<Address> = parse_address_list(header("To"))
+        .into_iter()
+        .filter(|a| keep(a))
+        .collect();
+    let cc = parse_address_list(header("Cc"))
+        .into_iter()
+        .filter(|a| keep(a))
+        .collect();
+
+    let sender_only = ReplyRecipients {
+        to: sender.clone(),
+        cc: Vec::new(),
+    };
+    let reply_all = ReplyRecipients {
+        to: sender.into_iter().chain(others).collect(),
+        cc,
+    };
+
+    match policy {
+        _ if sender_only.to.is_empty() => reply_all,
+        RecipientPolicy::SenderOnly => sender_only,
+        RecipientPolicy::ReplyAll => reply_all,
+        RecipientPolicy::ReplyAllCapped { max_recipients } if reply_all.len() > max_recipients => {
+            sender_only
+        }
+        RecipientPolicy::ReplyAllCapped { .. } => reply_all,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(pairs: &[(&str, &str)]) -> Vec<MessagePartHeaders> {
+        pairs
+            .iter()
+            .map(|(name, value)| MessagePartHeaders {
+                name: name.to_string(),
+                value: value.to_string(),
+            })
+            .collect()
+    }
+
+    fn emails(addresses: &[Address]) -> Vec<&str> {
+        addresses.iter().map(|a| a.email.as_str()).collect()
+    }
+
+    #[test]
+    fn reply_all_uses_reply_to_and_drops_own_addresses() {
+        let headers = headers(&[
+            ("From", r#""Doe, Jane" <jane@x.com>"#),
+            ("Reply-To", "list@x.com"),
+            ("To", "me@x.com, Ann <ann@x.com>"),
+            ("Cc", "alias@x.com, LIST@x.com, bob@x.com"),
+        ]);
+        let own = vec!["me@x.com".to_string(), "alias@x.com".to_string()];
+
+        let recipients = compute_reply_recipients(&headers, &own, RecipientPolicy::ReplyAll);
+        assert_eq!(emails(&recipients.to), ["list@x.com", "ann@x.com"]);
+        assert_eq!(emails(&recipients.cc), ["bob@x.com"]);
+    }
+
+    #[test]
+    fn aliases_that_are_substrings_do_not_remove_others() {
+        let headers = headers(&[("From", "joann@x.com"), ("To", "ann@x.com, me@x.com.au")]);
+        let own = vec!["ann@x.com".to_string(), "me@x.com".to_string()];
+
+        let recipients = compute_reply_recipients(&headers, &own, RecipientPolicy::ReplyAll);
+        assert_eq!(emails(&recipients.to), ["joann@x.com", "me@x.com.au"]);
+    }
+
+    #[test]
+    fn sender_only_and_capped_policies() {
+        let headers = headers(&[("From", "jane@x.com"), ("To", "me@x.com, a@x.com, b@x.com")]);
+        let own = vec!["me@x.com".to_string()];
+
+        let sender_only = compute_reply_recipients(&headers, &own, RecipientPolicy::SenderOnly);
+        assert_eq!(emails(&sender_only.to), ["jane@x.com"]);
+
+        let capped = compute_reply_recipients(
+            &headers,
+            &own,
+            RecipientPolicy::ReplyAllCapped { max_recipients: 2 },
+        );
+        assert_eq!(emails(&capped.to), ["jane@x.com"]);
+
+        let under_cap = compute_reply_recipients(
+            &headers,
+            &own,
+            RecipientPolicy::ReplyAllCapped { max_recipients: 3 },
+        );
+        assert_eq!(emails(&under_cap.to), ["jane@x.com", "a@x.com", "b@x.com"]);
+    }
+
+    #[test]
+    fn follow_up_to_own_mail_goes_to_original_recipients() {
+        let headers = headers(&[("From", "Me <me@x.com>"), ("To", "jane@x.com")]);
+        let own = vec!["me@x.com".to_string()];
+
+        let recipients = compute_reply_recipients(&headers, &own, RecipientPolicy::SenderOnly);
+        assert_eq!(emails(&recipients.to), ["jane@x.com"]);
+    }
+}
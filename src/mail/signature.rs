@@ -0,0 +1,156 @@
+use super::address::parse_address_list;
+use super::html;
+use super::quote::{escape_html, text_to_html};
+use crate::models::{AccountSettings, MessagePartHeaders, SendAs};
+
+/// The block that ends a reply, in both draft versions.
+pub struct Signature {
+    pub text: String,
+    pub html: String,
+}
+
+impl Signature {
+    /// The signature for a reply sent from `alias` (see `select_alias`): its
+    /// Gmail signature, else the account's configured `signature`, else just
+    /// its `sign_off_name`.
+    pub fn for_reply(alias: Option<&SendAs>, settings: &AccountSettings) -> Self {
+        let gmail_signature = alias
+            .and_then(|alias| alias.signature.as_deref())
+            .filter(|signature| !signature.trim().is_empty());
+
+        match (gmail_signature, &settings.signature) {
+            (Some(signature_html), _) => Self {
+                text: format!("-- \n{}", html::to_text(signature_html)),
+                html: format!(
+                    "<div dir=\"ltr\" class=\"gmail_signature\">{}</div>",
+                    signature_html
+                ),
+            },
+            (None, Some(signature)) => Self {
+                text: format!("-- \n{}", signature),
+                html: format!(
+                    "<div dir=\"ltr\" class=\"gmail_signature\">{}</div>",
+                    text_to_html(signature)
+                ),
+            },
+            (None, None) => Self {
+                text: settings.sign_off_name.clone(),
+                html: escape_html(&settings.sign_off_name),
+            },
+        }
+    }
+}
+
+/// The alias to reply from: the one among the message's `To` and `Cc`
+/// recipients, falling back to the default alias and then the primary
+/// address.
+pub fn select_alias<'a>(
+    send_as: &'a [SendAs],
+    headers: &[MessagePartHeaders],
+) -> Option<&'a SendAs> {
+    let recipients: Vec<String> = headers
+        .iter()
+        .filter(|h| h.name.eq_ignore_ascii_case("To") || h.name.eq_ignore_ascii_case("Cc"))
+        .flat_map(|h| parse_address_list(&h.value))
+        .map(|address| address.email)
+        .collect();
+
+    send_as
+        .iter()
+        .find(|alias| {
+            recipients
+                .iter()
+                .any(|email| email.eq_ignore_ascii_case(&alias.send_as_email))
+        })
+        .or_else(|| send_as.iter().find(|alias| alias.is_default))
+        .or_else(|| send_as.iter().find(|alias| alias.is_primary))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn alias(email: &str, is_primary: bool, is_default: bool) -> SendAs {
+        SendAs {
+            send_as_email: email.to_string(),
+            display_name: None,
+            signature: Some(format!("<b>{}</b>", email)),
+            is_primary,
+            is_default,
+        }
+    }
+
+    fn headers(pairs: &[(&str, &str)]) -> Vec<MessagePartHeaders> {
+        pairs
+            .iter()
+            .map(|(name, value)| MessagePartHeaders {
+                name: name.to_string(),
+                value: value.to_string(),
+            })
+            .collect()
+    }
+
+    fn selected<'a>(send_as: &'a [SendAs], pairs: &[(&str, &str)]) -> Option<&'a str> {
+        select_alias(send_as, &headers(pairs)).map(|alias| alias.send_as_email.as_str())
+    }
+
+    #[test]
+    fn alias_the_message_was_sent_to_is_selected() {
+        let send_as = [
+            alias("me@example.com", true, false),
+            alias("sales@example.com", false, true),
+            alias("support@example.com", false, false),
+        ];
+
+        assert_eq!(
+            selected(&send_as, &[("To", "Support <SUPPORT@example.com>")]),
+            Some("support@example.com")
+        );
+        assert_eq!(
+            selected(
+                &send_as,
+                &[("To", "list@example.org"), ("Cc", "me@example.com")]
+            ),
+            Some("me@example.com")
+        );
+    }
+
+    #[test]
+    fn default_alias_then_primary_address_are_the_fallbacks() {
+        let with_default = [
+            alias("me@example.com", true, false),
+            alias("sales@example.com", false, true),
+        ];
+        assert_eq!(
+            selected(&with_default, &[("To", "list@example.org")]),
+            Some("sales@example.com")
+        );
+
+        let without_default = [
+            alias("sales@example.com", false, false),
+            alias("me@example.com", true, false),
+        ];
+        assert_eq!(
+            selected(&without_default, &[("To", "list@example.org")]),
+            Some("me@example.com")
+        );
+        assert_eq!(selected(&[], &[("To", "me@example.com")]), None);
+    }
+
+    #[test]
+    fn signature_comes_from_the_alias_else_the_settings() {
+        let settings = AccountSettings {
+            signature: Some("Me\nExample Inc.".to_string()),
+            ..AccountSettings::default()
+        };
+        let sales = alias("sales@example.com", false, true);
+
+        let signature = Signature::for_reply(Some(&sales), &settings);
+        assert_eq!(signature.text, "-- \nsales@example.com");
+        assert!(signature.html.contains("<b>sales@example.com</b>"));
+
+        let signature = Signature::for_reply(None, &settings);
+        assert_eq!(signature.text, "-- \nMe\nExample Inc.");
+        assert!(signature.html.contains("Me<br>Example Inc."));
+    }
+}
@@ -0,0 +1,116 @@
+/// Reply and forward prefixes recognised at the start of a subject, compared
+/// after folding full-width characters and lowercasing.
+const PREFIXES: [&str; 19] = [
+    "re", "fw", "fwd", "aw", "wg", "sv", "vs", "tr", "rv", "antw", "odp", "ynt", "返信", "転送",
+    "回复", "转发", "答复", "회신", "전달",
+];
+
+/// Prefixes are short; a colon further in belongs to the subject itself.
+const MAX_PREFIX_CHARS: usize = 12;
+
+/// The subject for a reply to `subject`: every existing reply or forward
+/// prefix (`Re:`, `RE[2]:`, `Fwd:`, `AW:`, `返信:`, `Ｒｅ：`, ...) is removed
+/// and a single `Re: ` is put in front. Bracketed tags such as
+/// `【TPJP/DAWN】` or `[EXTERNAL]` are kept, in their original order.
+pub fn reply_subject(subject: &str) -> String {
+    let mut rest = subject.trim();
+    let mut tags = String::new();
+
+    loop {
+        if let Some(after) = strip_prefix(rest) {
+            rest = after.trim_start();
+        } else if let Some((tag, after)) = split_tag(rest) {
+            tags.push_str(tag);
+            if after.starts_with(char::is_whitespace) {
+                tags.push(' ');
+            }
+            rest = after.trim_start();
+        } else {
+            break;
+        }
+    }
+
+    format!("Re: {}{}", tags, rest).trim_end().to_string()
+}
+
+/// Returns `subject` after its leading reply or forward prefix, if it has one.
+fn strip_prefix(subject: &str) -> Option<&str> {
+    let (colon, c) = subject
+        .char_indices()
+        .take(MAX_PREFIX_CHARS)
+        .find(|(_, c)| *c == ':' || *c == '：')?;
+
+    let token: String = subject[..colon].chars().map(fold_width).collect();
+    let token = token.trim().to_lowercase();
+    // Some clients count replies: `Re[2]:`, `Re(2):`, `Re^2:`.
+    let token = token
+        .trim_end_matches(|c: char| c.is_ascii_digit() || matches!(c, '[' | ']' | '(' | ')' | '^'))
+        .trim_end();
+
+    PREFIXES
+        .contains(&token)
+        .then(|| &subject[colon + c.len_utf8()..])
+}
+
+/// Splits a leading `【...】` or `[...]` tag off `subject`.
+fn split_tag(subject: &str) -> Option<(&str, &str)> {
+    let close = match subject.chars().next()? {
+        '【' => '】',
+        '[' => ']',
+        '［' => '］',
+        _ => return None,
+    };
+    let end = subject.find(close)? + close.len_utf8();
+    Some((&subject[..end], &subject[end..]))
+}
+
+/// Maps full-width ASCII variants (`Ｒｅ`) and the ideographic space to their
+/// ASCII forms.
+fn fold_width(c: char) -> char {
+    match c {
+        '\u{FF01}'..='\u{FF5E}' => char::from_u32(c as u32 - 0xFEE0).unwrap_or(c),
+        '\u{3000}' => ' ',
+        _ => c,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn repeated_prefixes_collapse_to_one() {
+        assert_eq!(reply_subject("Lunch"), "Re: Lunch");
+        assert_eq!(reply_subject("Re: Re: Lunch"), "Re: Lunch");
+        assert_eq!(reply_subject("RE[2]: Fwd: AW: Lunch"), "Re: Lunch");
+        assert_eq!(reply_subject("  re:Lunch  "), "Re: Lunch");
+    }
+
+    #[test]
+    fn full_width_and_localized_prefixes_are_removed() {
+        assert_eq!(reply_subject("Ｒｅ：打ち合わせ"), "Re: 打ち合わせ");
+        assert_eq!(reply_subject("返信: Ｒｅ： 打ち合わせ"), "Re: 打ち合わせ");
+        assert_eq!(reply_subject("회신: 회의"), "Re: 회의");
+    }
+
+    #[test]
+    fn bracketed_tags_are_kept_in_order() {
+        assert_eq!(
+            reply_subject("【TPJP/DAWN】Re: 見積もりの件"),
+            "Re: 【TPJP/DAWN】見積もりの件"
+        );
+        assert_eq!(
+            reply_subject("Re: [EXTERNAL] 【TPJP/DAWN】 Re: Quote"),
+            "Re: [EXTERNAL] 【TPJP/DAWN】 Quote"
+        );
+    }
+
+    #[test]
+    fn colons_inside_the_subject_are_kept() {
+        assert_eq!(
+            reply_subject("Agenda for Friday: budget"),
+            "Re: Agenda for Friday: budget"
+        );
+        assert_eq!(reply_subject("Meeting at 10:30"), "Re: Meeting at 10:30");
+    }
+}
@@ -0,0 +1,296 @@
+//! Compact transcripts of a conversation, so a reply can take into account
+//! what was already said earlier in its thread.
+
+use super::body;
+use crate::models::Message;
+
+const ENTRY_SEPARATOR: &str = "\n\n=====\n\n";
+
+/// Rough token count: about four ASCII characters per token, while other
+/// scripts (Japanese in particular) take about one token per character.
+pub fn estimate_tokens(text: &str) -> usize {
+    text.chars()
+        .map(|c| if c.is_ascii() { 1 } else { 4 })
+        .sum::<usize>()
+        .div_ceil(4)
+}
+
+/// The messages of a thread received before `current_message_id`, oldest
+/// first, each headed by its `From` and `Date` and with quoted history
+/// removed. Drafts and messages repeating an earlier one are left out, and
+/// so are the oldest messages once the transcript would exceed
+/// `token_budget`. Empty when nothing came before.
+pub fn transcript(messages: &[Message], current_message_id: &str, token_budget: usize) -> String {
+    let mut ordered: Vec<&Message> = messages.iter().collect();
+    ordered.sort_by_key(|message| received_at(message));
+    if let Some(current) = ordered.iter().position(|m| m.id == current_message_id) {
+        ordered.truncate(current);
+    }
+
+    let mut seen: Vec<String> = Vec::new();
+    let mut entries = Vec::new();
+    for message in ordered {
+        if message.label_ids.iter().any(|label| label == "DRAFT") {
+            continue;
+        }
+        let Some(text) = body::extract_text(&message.payload) else {
+            continue;
+        };
+        let text = strip_quoted(&text);
+        if text.is_empty() || seen.contains(&text) {
+            continue;
+        }
+
+        let header = |name: &str| {
+            message
+                .payload
+                .headers
+                .iter()
+                .find(|h| h.name.eq_ignore_ascii_case(name))
+                .map_or("", |h| h.value.as_str())
+        };
+        entries.push(format!(
+            "From: {}\nDate: {}\n\n{}",
+            header("From"),
+            header("Date"),
+            text
+        ));
+        seen.push(text);
+    }
+
+    // Keep the newest entries that fit; a single entry over the budget is
+    // cut short rather than dropped.
+    let mut kept = Vec::new();
+    let mut used = 0;
+    for entry in entries.iter().rev() {
+        let tokens = estimate_tokens(entry);
+        if used + tokens > token_budget {
+            if kept.is_empty() {
+                kept.push(truncate_to_tokens(entry, token_budget));
+            }
+            break;
+        }
+        used += tokens;
+        kept.push(entry.clone());
+    }
+    let omitted = entries.len() - kept.len();
+    kept.reverse();
+
+    let transcript = kept.join(ENTRY_SEPARATOR);
+    if omitted > 0 {
+        format!(
+            "[{} earlier messages omitted]{}{}",
+            omitted, ENTRY_SEPARATOR, transcript
+        )
+    } else {
+        transcript
+    }
+}
+
+/// `text` without its quoted history: lines starting with `>` are dropped,
+/// and everything from an attribution line (`On ... wrote:`), an
+/// `-----Original Message-----` separator or an Outlook `From:`/`Sent:`
+/// block onwards is cut.
+pub fn strip_quoted(text: &str) -> String {
+    let lines: Vec<&str> = text.lines().collect();
+    let end = (0..lines.len())
+        .find(|&i| starts_history(&lines, i))
+        .unwrap_or(lines.len());
+
+    lines[..end]
+        .iter()
+        .filter(|line| !line.trim_start().starts_with('>'))
+        .copied()
+        .collect::<Vec<_>>()
+        .join("\n")
+        .trim()
+        .to_string()
+}
+
+fn starts_history(lines: &[&str], i: usize) -> bool {
+    let line = lines[i].trim();
+    let next = |n: usize| lines.get(i + n).map_or("", |l| l.trim());
+
+    is_attribution(line)
+        // Long attributions are wrapped onto a second line.
+        || (line.starts_with("On ") && is_attribution(next(1)))
+        // `-----Original Message-----`, `-----元のメッセージ-----`
+        || (line.starts_with("-----")
+            && line.ends_with("-----")
+            && !line.trim_matches('-').trim().is_empty())
+        || (line.starts_with("From:")
+            && (1..=3).any(|n| {
+                ["Sent:", "Date:", "送信日時:"]
+                    .iter()
+                    .any(|h| next(n).starts_with(h))
+            }))
+}
+
+fn is_attribution(line: &str) -> bool {
+    line.ends_with("wrote:")
+        || line.ends_with("書きました:")
+        || line.ends_with("書きました：")
+        // Gmail in Japanese: `2025年10月14日(火) 9:12 Jane <jane@example.com>:`
+        || (line.contains('年') && line.ends_with(">:"))
+}
+
+/// Epoch milliseconds; messages without a date sort first, in the order given.
+fn received_at(message: &Message) -> u64 {
+    message
+        .internal_date
+        .as_deref()
+        .and_then(|date| date.parse().ok())
+        .unwrap_or(0)
+}
+
+fn truncate_to_tokens(text: &str, token_budget: usize) -> String {
+    let mut units = 0;
+    let mut truncated = String::new();
+    for c in text.chars() {
+        units += if c.is_ascii() { 1 } else { 4 };
+        if units > token_budget * 4 {
+            truncated.push('…');
+            break;
+        }
+        truncated.push(c);
+    }
+    truncated
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{MessagePart, MessagePartBody, MessagePartHeaders};
+    use base64::{engine::general_purpose::URL_SAFE, Engine as _};
+
+    fn message(id: &str, received: u64, from: &str, text: &str) -> Message {
+        Message {
+            id: id.to_string(),
+            snippet: String::new(),
+            payload: MessagePart {
+                part_id: String::new(),
+                mime_type: "text/plain".to_string(),
+                filename: String::new(),
+                headers: vec![
+                    MessagePartHeaders {
+                        name: "From".to_string(),
+                        value: from.to_string(),
+                    },
+                    MessagePartHeaders {
+                        name: "Date".to_string(),
+                        value: format!("day {}", received),
+                    },
+                ],
+                body: MessagePartBody {
+                    size: text.len() as u32,
+                    data: Some(URL_SAFE.encode(text)),
+                },
+                parts: None,
+            },
+            label_ids: Vec::new(),
+            internal_date: Some(received.to_string()),
+        }
+    }
+
+    #[test]
+    fn strips_quoted_lines_and_attributed_history() {
+        let text = "Friday works.\n> Can we meet?\n\nOn Mon, Oct 13, 2025 at 9:12 AM Jane <jane@x.com> wrote:\n> Can we meet?";
+        assert_eq!(strip_quoted(text), "Friday works.");
+
+        let wrapped =
+            "Sure.\n\nOn Mon, Oct 13, 2025 at 9:12 AM Jane Doe\n<jane@x.com> wrote:\nCan we meet?";
+        assert_eq!(strip_quoted(wrapped), "Sure.");
+    }
+
+    #[test]
+    fn strips_outlook_and_japanese_history() {
+        let outlook = "Attached.\n\nFrom: Jane Doe <jane@x.com>\nSent: Monday, October 13, 2025 9:12 AM\nTo: me@x.com\nSubject: Quote";
+        assert_eq!(strip_quoted(outlook), "Attached.");
+
+        let original = "OK.\n-----Original Message-----\nPlease send it.";
+        assert_eq!(strip_quoted(original), "OK.");
+
+        let gmail_ja =
+            "承知しました。\n\n2025年10月14日(火) 9:12 Jane <jane@x.com>:\n> お願いします。";
+        assert_eq!(strip_quoted(gmail_ja), "承知しました。");
+    }
+
+    #[test]
+    fn keeps_separator_lines_without_text() {
+        assert_eq!(strip_quoted("Total\n-----\n42"), "Total\n-----\n42");
+    }
+
+    #[test]
+    fn transcript_lists_earlier_messages_oldest_first() {
+        let messages = vec![
+            message("m3", 3, "Jane <jane@x.com>", "Thanks!"),
+            message("m1", 1, "Jane <jane@x.com>", "Can we meet on Friday?"),
+            message(
+                "m2",
+                2,
+                "Me <me@x.com>",
+                "Friday works.\n\nOn Mon Jane <jane@x.com> wrote:\n> Can we meet on Friday?",
+            ),
+        ];
+
+        assert_eq!(
+            transcript(&messages, "m3", 1000),
+            "From: Jane <jane@x.com>\nDate: day 1\n\nCan we meet on Friday?\
+             \n\n=====\n\n\
+             From: Me <me@x.com>\nDate: day 2\n\nFriday works."
+        );
+        assert_eq!(transcript(&messages, "m1", 1000), "");
+    }
+
+    #[test]
+    fn transcript_leaves_out_drafts_and_repeats() {
+        let mut draft = message("m2", 2, "Me <me@x.com>", "Draft reply");
+        draft.label_ids = vec!["DRAFT".to_string()];
+        let messages = vec![
+            message("m1", 1, "Jane <jane@x.com>", "Hello"),
+            draft,
+            message("m3", 3, "Jane <jane@x.com>", "Hello"),
+            message("m4", 4, "Jane <jane@x.com>", "Any news?"),
+        ];
+
+        assert_eq!(
+            transcript(&messages, "m4", 1000),
+            "From: Jane <jane@x.com>\nDate: day 1\n\nHello"
+        );
+    }
+
+    #[test]
+    fn transcript_drops_the_oldest_messages_over_the_budget() {
+        let messages = vec![
+            message("m1", 1, "a@x.com", &"old ".repeat(50)),
+            message("m2", 2, "b@x.com", &"mid ".repeat(50)),
+            message("m3", 3, "c@x.com", "new"),
+            message("m4", 4, "d@x.com", "current"),
+        ];
+
+        let text = transcript(&messages, "m4", 80);
+        assert!(text.starts_with("[1 earlier messages omitted]\n\n=====\n\nFrom: b@x.com"));
+        assert!(text.ends_with("From: c@x.com\nDate: day 3\n\nnew"));
+        assert!(!text.contains("old"));
+    }
+
+    #[test]
+    fn transcript_cuts_a_single_message_over_the_budget() {
+        let messages = vec![
+            message("m1", 1, "a@x.com", &"word ".repeat(100)),
+            message("m2", 2, "b@x.com", "current"),
+        ];
+
+        let text = transcript(&messages, "m2", 20);
+        assert!(text.starts_with("From: a@x.com"));
+        assert!(text.ends_with('…'));
+        assert!(estimate_tokens(&text) <= 21);
+    }
+
+    #[test]
+    fn estimates_more_tokens_for_japanese() {
+        assert_eq!(estimate_tokens("abcdefgh"), 2);
+        assert_eq!(estimate_tokens("日本語"), 3);
+        assert_eq!(estimate_tokens(""), 0);
+    }
+}
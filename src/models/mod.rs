@@ -1,4 +1,5 @@
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 
 #[derive(Deserialize, Debug)]
 pub struct GoogleTokenResponse {
@@ -9,9 +10,27 @@ pub struct GoogleTokenResponse {
 }
 
 #[derive(Deserialize, Debug)]
+pub struct AuthorizationCodeResponse {
+    pub access_token: String,
+    pub expires_in: u32,
+    /// Only present when the user granted offline access in this consent.
+    pub refresh_token: Option<String>,
+    pub scope: String,
+    pub token_type: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Profile {
+    pub email_address: String,
+    pub history_id: String,
+}
+
+#[derive(Deserialize, Debug, Default)]
 #[serde(rename_all = "camelCase")]
 pub struct MessageListResponse {
     pub messages: Option<Vec<MessageId>>,
+    pub next_page_token: Option<String>,
 }
 
 #[derive(Deserialize, Debug, Clone)]
@@ -21,14 +40,27 @@ pub struct MessageId {
     pub thread_id: String,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
 pub struct Message {
     pub id: String,
     pub snippet: String,
     pub payload: MessagePart,
+    #[serde(default)]
+    pub label_ids: Vec<String>,
+    /// When Gmail received the message, in epoch milliseconds.
+    #[serde(default)]
+    pub internal_date: Option<String>,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone)]
+pub struct Thread {
+    pub id: String,
+    #[serde(default)]
+    pub messages: Vec<Message>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct MessagePart {
     pub part_id: String,
@@ -39,13 +71,13 @@ pub struct MessagePart {
     pub parts: Option<Vec<MessagePart>>,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone)]
 pub struct MessagePartHeaders {
     pub name: String,
     pub value: String,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone)]
 pub struct MessagePartBody {
     pub size: u32,
     pub data: Option<String>,
@@ -54,8 +86,17 @@ pub struct MessagePartBody {
 // --- Gemini Structs ---
 // These structs are for building the request we send TO Gemini.
 #[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
 pub struct GeminiRequest {
     pub contents: Vec<Content>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub generation_config: Option<GenerationConfig>,
+}
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct GenerationConfig {
+    pub response_mime_type: String,
+    pub response_schema: serde_json::Value,
 }
 #[derive(Serialize, Debug)]
 pub struct Content {
@@ -96,12 +137,132 @@ pub struct DraftMessage {
     pub raw: String,
 }
 
+/// The part of a `users.drafts.create` response the pipeline keeps.
+#[derive(Deserialize, Debug)]
+pub struct Draft {
+    pub id: String,
+}
+
 #[derive(Serialize, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct ModifyMessageRequest {
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub add_label_ids: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
     pub remove_label_ids: Vec<String>,
 }
 
+// --- Send-As Structs ---
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct SendAs {
+    pub send_as_email: String,
+    pub display_name: Option<String>,
+    /// HTML signature configured in Gmail for this alias.
+    pub signature: Option<String>,
+    #[serde(default)]
+    pub is_primary: bool,
+    #[serde(default)]
+    pub is_default: bool,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct SendAsListResponse {
+    pub send_as: Option<Vec<SendAs>>,
+}
+
+// --- Label Structs ---
+#[derive(Deserialize, Debug, Clone)]
+pub struct Label {
+    pub id: String,
+    pub name: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct LabelListResponse {
+    pub labels: Option<Vec<Label>>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateLabelRequest {
+    pub name: String,
+    pub label_list_visibility: String,
+    pub message_list_visibility: String,
+}
+
+/// The state the bot left a message in, shown to the user as a Gmail label.
+/// A message carries at most one of these at a time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BotLabel {
+    Drafted,
+    NeedsFile,
+    Skipped,
+    /// Processing failed; the message is picked up again on the next run.
+    Error,
+    /// Processing failed on every attempt; the bot has given up on it.
+    Failed,
+}
+
+impl BotLabel {
+    pub const ALL: [BotLabel; 5] = [
+        BotLabel::Drafted,
+        BotLabel::NeedsFile,
+        BotLabel::Skipped,
+        BotLabel::Error,
+        BotLabel::Failed,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            BotLabel::Drafted => "Bot/Drafted",
+            BotLabel::NeedsFile => "Bot/NeedsFile",
+            BotLabel::Skipped => "Bot/Skipped",
+            BotLabel::Error => "Bot/Error",
+            BotLabel::Failed => "Bot/Failed",
+        }
+    }
+
+    /// Whether the bot is done with a message in this state.
+    pub fn is_final(self) -> bool {
+        self != BotLabel::Error
+    }
+}
+
+impl From<LedgerAction> for BotLabel {
+    fn from(action: LedgerAction) -> Self {
+        match action {
+            LedgerAction::Drafted => BotLabel::Drafted,
+            LedgerAction::Skipped => BotLabel::Skipped,
+            LedgerAction::NeedsFile => BotLabel::NeedsFile,
+            LedgerAction::Failed => BotLabel::Failed,
+        }
+    }
+}
+
+/// A reply draft as the pipeline hands it to a `MailProvider`.
+#[derive(Debug, Clone)]
+pub struct DraftReply {
+    pub thread_id: String,
+    /// The send-as alias to send from, formatted for the `From` header;
+    /// Gmail uses the default alias when `None`.
+    pub from: Option<String>,
+    pub to: String,
+    pub cc: String,
+    /// The reply's own subject, including its `Re: ` prefix.
+    pub subject: String,
+    /// Plain-text body, including the quoted original.
+    pub body: String,
+    /// HTML version of `body`; the draft is plain text only without it.
+    pub html_body: Option<String>,
+    pub attachments: Vec<Attachment>,
+    /// `Message-ID` of the message being replied to.
+    pub in_reply_to: Option<String>,
+    /// `References` header of the message being replied to.
+    pub references: Option<String>,
+}
+
 // Google Drive Structs
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
@@ -109,7 +270,7 @@ pub struct FileListResponse {
     pub files: Vec<DriveFile>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct DriveFile {
     pub id: String,
@@ -144,8 +305,516 @@ pub struct EmbeddingValues {
 }
 
 // --- Vectorize Structs ---
+/// Metadata of a vector in the context index: a knowledge snippet the
+/// drafting prompt can draw on.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ContextualDocument {
     pub text: String,
+    /// Unix seconds.
+    pub created_at: i64,
+    /// Where the text came from, e.g. a Drive file name.
+    #[serde(default)]
+    pub source: String,
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+// --- Knowledge Ingestion Structs ---
+/// Body of `POST /knowledge`: exactly one of `text`, `markdown` or
+/// `drive_file_id`.
+#[derive(Deserialize, Debug)]
+pub struct IngestRequest {
+    pub text: Option<String>,
+    pub markdown: Option<String>,
+    pub drive_file_id: Option<String>,
+    /// Defaults to the Drive file name, or `text` / `markdown`.
+    pub source: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+/// One ingested text, kept in KV so it can be listed and its vectors
+/// deleted again.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct KnowledgeSource {
+    pub id: String,
+    /// The mailbox whose drafts draw on it.
+    pub account: String,
+    pub source: String,
+    pub tags: Vec<String>,
+    /// Unix seconds.
     pub created_at: i64,
+    /// Number of chunks stored in the context index.
+    pub chunks: usize,
+}
+
+impl KnowledgeSource {
+    /// IDs of the source's vectors, `<id>:<chunk>`.
+    pub fn vector_ids(&self) -> Vec<String> {
+        (0..self.chunks)
+            .map(|chunk| format!("{}:{}", self.id, chunk))
+            .collect()
+    }
+}
+
+// --- Scheduled Run Structs ---
+#[derive(Serialize, Debug)]
+pub struct RunReport {
+    pub trigger: String,
+    pub started_at: String,
+    pub finished_at: String,
+    pub success: bool,
+    pub error: Option<String>,
+    /// Counts only; subjects and draft text are never stored.
+    pub stats: RunStats,
+}
+
+/// What a pipeline run did, summed over all accounts.
+#[derive(Serialize, Debug, Default, Clone)]
+pub struct RunStats {
+    pub accounts: usize,
+    pub failed_accounts: usize,
+    pub drafted: usize,
+    pub needs_file: usize,
+    pub skipped: usize,
+    pub errors: usize,
+    /// Messages given up on after repeated failures.
+    pub failed: usize,
+}
+
+impl RunStats {
+    /// Counts a message that ended up in `state`.
+    pub fn count(&mut self, state: BotLabel) {
+        match state {
+            BotLabel::Drafted => self.drafted += 1,
+            BotLabel::NeedsFile => self.needs_file += 1,
+            BotLabel::Skipped => self.skipped += 1,
+            BotLabel::Error => self.errors += 1,
+            BotLabel::Failed => self.failed += 1,
+        }
+    }
+}
+
+// --- Gmail Push Notification Structs ---
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct WatchRequest {
+    pub topic_name: String,
+    pub label_ids: Vec<String>,
+    pub label_filter_behavior: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct WatchResponse {
+    pub history_id: String,
+    /// Epoch milliseconds, serialized by Gmail as a string.
+    pub expiration: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct HistoryListResponse {
+    pub history: Option<Vec<HistoryRecord>>,
+    pub next_page_token: Option<String>,
+    pub history_id: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct HistoryRecord {
+    pub id: String,
+    pub messages_added: Option<Vec<HistoryMessageAdded>>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct HistoryMessageAdded {
+    pub message: HistoryMessage,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct HistoryMessage {
+    pub id: String,
+    pub thread_id: String,
+    pub label_ids: Option<Vec<String>>,
+}
+
+// Pub/Sub push payload: https://cloud.google.com/pubsub/docs/push
+#[derive(Deserialize, Debug)]
+pub struct PubSubPushRequest {
+    pub message: PubSubMessage,
+    pub subscription: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct PubSubMessage {
+    pub data: String,
+    pub message_id: String,
+}
+
+/// The JSON object base64-encoded in `PubSubMessage.data` by Gmail.
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct GmailNotification {
+    pub email_address: String,
+    pub history_id: u64,
+}
+
+// --- Account Structs ---
+/// Per-mailbox settings stored as JSON under `account:<email>:settings`.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct AccountSettings {
+    /// Full name of the mailbox owner, used when describing who the bot works
+    /// for. Taken from the Gmail display name when empty.
+    pub owner_name: String,
+    /// Name the owner goes by in replies; the signature when no other is set.
+    /// Taken from the Gmail display name when empty.
+    pub sign_off_name: String,
+    /// Plain-text signature used when the Gmail send-as alias has none.
+    pub signature: Option<String>,
+    /// Owner-specific facts added to the drafting instructions.
+    pub context_notes: Vec<String>,
+    /// Overrides the deployment-wide LLM backend for this mailbox.
+    pub llm: Option<LlmSettings>,
+    /// Who drafted replies are addressed to.
+    pub recipient_policies: RecipientPolicies,
+    /// Also remove `UNREAD` once the bot is done with a message. Off by
+    /// default so the owner still sees which mail they haven't read; the
+    /// `Bot/*` labels keep handled mail out of the next run either way.
+    pub mark_as_read: bool,
+}
+
+impl AccountSettings {
+    /// Settings of the original single-mailbox deployment, still used for the
+    /// `USER_EMAIL` account when it has none stored.
+    pub fn legacy() -> Self {
+        Self {
+            owner_name: "John Tashiro".to_string(),
+            sign_off_name: "John".to_string(),
+            context_notes: vec![
+                r#""A6" refers to a team. In a Japanese reply, use "A6チーム"."#.to_string(),
+            ],
+            ..Self::default()
+        }
+    }
+
+    /// Fills in an empty `owner_name` and `sign_off_name` from the mailbox's
+    /// display name (its first word for the sign-off), or from the local
+    /// part of `email` when there is no display name.
+    pub fn fill_names(&mut self, display_name: Option<&str>, email: &str) {
+        let display_name = display_name
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .unwrap_or_else(|| email.split('@').next().unwrap_or(email));
+
+        if self.owner_name.trim().is_empty() {
+            self.owner_name = display_name.to_string();
+        }
+        if self.sign_off_name.trim().is_empty() {
+            self.sign_off_name = display_name
+                .split_whitespace()
+                .next()
+                .unwrap_or(display_name)
+                .to_string();
+        }
+    }
+}
+
+/// Which of the original recipients a reply goes to.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum RecipientPolicy {
+    /// Only the sender (or their `Reply-To`).
+    SenderOnly,
+    /// The sender plus everyone in `To` and `Cc`.
+    #[default]
+    ReplyAll,
+    /// Reply-all, unless that would address more than `max_recipients`
+    /// people, in which case only the sender.
+    ReplyAllCapped { max_recipients: usize },
+}
+
+/// Recipient policy rules, e.g.
+/// `{"default": {"mode": "reply_all"}, "by_sender_domain": {"example.com": {"mode": "sender_only"}}}`.
+/// A sender-domain rule wins over an intent rule, which wins over `default`;
+/// among domain rules the most specific one applies.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct RecipientPolicies {
+    pub default: RecipientPolicy,
+    pub by_intent: HashMap<Intent, RecipientPolicy>,
+    /// Keyed by domain; a rule also covers the domain's subdomains.
+    pub by_sender_domain: HashMap<String, RecipientPolicy>,
+}
+
+impl RecipientPolicies {
+    /// The policy for a message classified as `intent` from `sender_email`.
+    pub fn select(&self, intent: Intent, sender_email: &str) -> RecipientPolicy {
+        let domain = sender_email
+            .rsplit_once('@')
+            .map_or("", |(_, domain)| domain)
+            .to_lowercase();
+        // The most specific (longest) matching domain wins, so
+        // `lists.example.com` overrides `example.com` for its senders.
+        let domain_rule = self
+            .by_sender_domain
+            .iter()
+            .map(|(rule, policy)| (rule.trim_start_matches('@').to_lowercase(), policy))
+            .filter(|(rule, _)| domain == *rule || domain.ends_with(&format!(".{}", rule)))
+            .max_by_key(|(rule, _)| rule.len());
+
+        match domain_rule {
+            Some((_, policy)) => *policy,
+            None => self.by_intent.get(&intent).copied().unwrap_or(self.default),
+        }
+    }
+}
+
+// --- LLM Backend Structs ---
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum LlmProvider {
+    #[default]
+    Gemini,
+    /// Any server exposing `/chat/completions` and `/embeddings`, such as
+    /// Ollama or llama.cpp.
+    OpenaiCompatible,
+}
+
+/// Which LLM backend to use. Unset fields fall back to the provider's defaults.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct LlmSettings {
+    pub provider: LlmProvider,
+    pub base_url: Option<String>,
+    pub model: Option<String>,
+    pub embedding_model: Option<String>,
+}
+
+// OpenAI-compatible chat completions and embeddings.
+#[derive(Serialize, Debug)]
+pub struct ChatCompletionRequest {
+    pub model: String,
+    pub messages: Vec<ChatMessage>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response_format: Option<serde_json::Value>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ChatMessage {
+    pub role: String,
+    pub content: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ChatCompletionResponse {
+    pub choices: Vec<ChatChoice>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ChatChoice {
+    pub message: ChatMessage,
+}
+
+#[derive(Serialize, Debug)]
+pub struct OpenAiEmbeddingRequest {
+    pub model: String,
+    pub input: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct OpenAiEmbeddingResponse {
+    pub data: Vec<OpenAiEmbedding>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct OpenAiEmbedding {
+    pub embedding: Vec<f32>,
+}
+
+// --- Classification Structs ---
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum Intent {
+    Reply,
+    NoReply,
+    FileRequest,
+}
+
+/// The classifier's verdict on an incoming email.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Classification {
+    pub intent: Intent,
+    pub confidence: f32,
+    /// ISO 639-1 code, e.g. `ja` or `en`.
+    pub language: String,
+    pub reason: String,
+}
+
+impl Classification {
+    /// Parses the model's JSON reply.
+    pub fn from_llm_output(text: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(strip_code_fence(text))
+    }
+}
+
+/// The part of a `Classification` the ledger keeps. The `reason` describes
+/// the email's content, so it is left out.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LedgerClassification {
+    pub intent: Intent,
+    pub confidence: f32,
+}
+
+impl From<&Classification> for LedgerClassification {
+    fn from(classification: &Classification) -> Self {
+        Self {
+            intent: classification.intent,
+            confidence: classification.confidence,
+        }
+    }
+}
+
+/// The documents a file request asks for, each with Drive search keywords.
+#[derive(Deserialize, Debug, Clone)]
+pub struct RequestedDocuments {
+    pub documents: Vec<RequestedDocument>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct RequestedDocument {
+    pub name: String,
+    pub keywords: Vec<String>,
+}
+
+impl RequestedDocuments {
+    /// Parses the model's JSON reply.
+    pub fn from_llm_output(text: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(strip_code_fence(text))
+    }
+}
+
+/// Removes a Markdown code fence around a JSON reply, which models without
+/// schema support sometimes add.
+fn strip_code_fence(text: &str) -> &str {
+    let trimmed = text.trim();
+    trimmed
+        .strip_prefix("```json")
+        .or_else(|| trimmed.strip_prefix("```"))
+        .and_then(|rest| rest.strip_suffix("```"))
+        .unwrap_or(trimmed)
+        .trim()
+}
+
+// --- Processed-Message Ledger Structs ---
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum LedgerAction {
+    /// A reply draft was created; `draft_id` is set.
+    Drafted,
+    /// The classifier decided no reply is needed.
+    Skipped,
+    /// A file was requested but no single matching Drive file was found.
+    NeedsFile,
+    /// Processing failed `MAX_ATTEMPTS` times; the message is not retried.
+    Failed,
+}
+
+/// What the pipeline did with a message, stored so it is never handled twice.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LedgerEntry {
+    pub message_id: String,
+    /// `None` when processing failed before the message was classified.
+    #[serde(default)]
+    pub classification: Option<LedgerClassification>,
+    pub action: LedgerAction,
+    pub draft_id: Option<String>,
+    /// RFC 3339 timestamp.
+    pub processed_at: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classification_is_parsed_with_or_without_a_code_fence() {
+        let json = r#"{"intent": "FILE_REQUEST", "confidence": 0.9, "language": "ja", "reason": "Asks for the quote."}"#;
+
+        for text in [
+            json.to_string(),
+            format!("```json\n{}\n```", json),
+            format!("```\n{}\n```\n", json),
+        ] {
+            let classification = Classification::from_llm_output(&text).unwrap();
+            assert_eq!(classification.intent, Intent::FileRequest);
+            assert_eq!(classification.language, "ja");
+        }
+    }
+
+    #[test]
+    fn unknown_intent_is_rejected() {
+        let text = r#"{"intent": "MAYBE", "confidence": 0.5, "language": "en", "reason": ""}"#;
+        assert!(Classification::from_llm_output(text).is_err());
+    }
+
+    #[test]
+    fn names_come_from_the_display_name_or_address() {
+        let mut settings = AccountSettings::default();
+        settings.fill_names(Some("Jane Doe"), "jane@x.com");
+        assert_eq!(settings.owner_name, "Jane Doe");
+        assert_eq!(settings.sign_off_name, "Jane");
+
+        let mut settings = AccountSettings::default();
+        settings.fill_names(Some(" "), "bob.smith@x.com");
+        assert_eq!(settings.owner_name, "bob.smith");
+        assert_eq!(settings.sign_off_name, "bob.smith");
+
+        let mut settings = AccountSettings {
+            sign_off_name: "JD".to_string(),
+            ..AccountSettings::default()
+        };
+        settings.fill_names(Some("Jane Doe"), "jane@x.com");
+        assert_eq!(settings.owner_name, "Jane Doe");
+        assert_eq!(settings.sign_off_name, "JD");
+    }
+
+    #[test]
+    fn most_specific_domain_rule_wins() {
+        let mut policies = RecipientPolicies::default();
+        policies
+            .by_sender_domain
+            .insert("example.com".to_string(), RecipientPolicy::ReplyAll);
+        policies
+            .by_sender_domain
+            .insert("lists.example.com".to_string(), RecipientPolicy::SenderOnly);
+        policies.by_intent.insert(
+            Intent::Reply,
+            RecipientPolicy::ReplyAllCapped { max_recipients: 3 },
+        );
+
+        assert_eq!(
+            policies.select(Intent::Reply, "bot@lists.example.com"),
+            RecipientPolicy::SenderOnly
+        );
+        assert_eq!(
+            policies.select(Intent::Reply, "bot@news.lists.example.com"),
+            RecipientPolicy::SenderOnly
+        );
+        assert_eq!(
+            policies.select(Intent::Reply, "jane@example.com"),
+            RecipientPolicy::ReplyAll
+        );
+        assert_eq!(
+            policies.select(Intent::Reply, "jane@notexample.com"),
+            RecipientPolicy::ReplyAllCapped { max_recipients: 3 }
+        );
+        assert_eq!(
+            policies.select(Intent::NoReply, "jane@other.org"),
+            RecipientPolicy::ReplyAll
+        );
+    }
 }
@@ -0,0 +1,208 @@
+pub mod token;
+
+use crate::{accounts, gmail};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use token::TokenManager;
+use worker::*;
+
+/// Scopes the pipeline needs: reading, labelling and drafting mail, and
+/// reading Drive files to attach.
+pub const REQUIRED_SCOPES: [&str; 2] = [
+    "https://www.googleapis.com/auth/gmail.modify",
+    "https://www.googleapis.com/auth/drive.readonly",
+];
+
+/// How long a `state` nonce (and its PKCE verifier) stays valid in KV.
+const OAUTH_STATE_TTL_SECONDS: u64 = 10 * 60;
+
+/// What `/oauth/start` remembers under `oauth_state:<state>` for the callback.
+#[derive(Serialize, Deserialize, Debug)]
+struct PendingAuthorization {
+    account: String,
+    code_verifier: String,
+}
+
+/// Starts the authorization-code flow for the mailbox given in `?account=`
+/// (defaulting to `USER_EMAIL`). Protected by `WORKER_AUTH_TOKEN`, since
+/// completing the flow replaces the refresh token the bot runs with.
+pub async fn handle_start(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    if !crate::is_authorized(&req, &ctx.env) {
+        return Response::error("Unauthorized", 401);
+    }
+
+    let account = req
+        .url()?
+        .query_pairs()
+        .find(|(key, _)| key == "account")
+        .map(|(_, value)| value.into_owned())
+        .or_else(|| ctx.env.secret("USER_EMAIL").ok().map(|s| s.to_string()));
+    let account = match account {
+        Some(account) => account,
+        None => return Response::error("Missing `account` parameter.", 400),
+    };
+
+    let client_id = ctx.env.secret("GMAIL_CLIENT_ID")?.to_string();
+    let redirect_uri = redirect_uri(&req)?;
+
+    let state = random_token()?;
+    let code_verifier = random_token()?;
+    let code_challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(code_verifier.as_bytes()));
+
+    let kv = ctx.env.kv("GMAIL_AUTH")?;
+    let pending = PendingAuthorization {
+        account: account.clone(),
+        code_verifier,
+    };
+    kv.put(&format!("oauth_state:{}", state), pending)?
+        .expiration_ttl(OAUTH_STATE_TTL_SECONDS)
+        .execute()
+        .await?;
+
+    let scope = REQUIRED_SCOPES.join(" ");
+    let auth_url = Url::parse_with_params(
+        "https://accounts.google.com/o/oauth2/v2/auth",
+        &[
+            ("client_id", client_id.as_str()),
+            ("redirect_uri", redirect_uri.as_str()),
+            ("response_type", "code"),
+            ("scope", scope.as_str()),
+            // `offline` + `consent` makes Google issue a refresh token even
+            // when the user has authorized this client before.
+            ("access_type", "offline"),
+            ("prompt", "consent"),
+            ("include_granted_scopes", "true"),
+            ("login_hint", account.as_str()),
+            ("state", state.as_str()),
+            ("code_challenge", code_challenge.as_str()),
+            ("code_challenge_method", "S256"),
+        ],
+    )?;
+
+    Response::redirect(auth_url)
+}
+
+/// Finishes the flow: validates `state`, exchanges the code, stores the
+/// refresh token under the account's key in `GMAIL_AUTH` and registers the
+/// account for pipeline runs.
+pub async fn handle_callback(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let url = req.url()?;
+    let param = |name: &str| {
+        url.query_pairs()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.into_owned())
+    };
+
+    if let Some(error) = param("error") {
+        return Response::error(format!("Authorization was not granted: {}", error), 400);
+    }
+    let (code, state) = match (param("code"), param("state")) {
+        (Some(code), Some(state)) => (code, state),
+        _ => return Response::error("Missing `code` or `state` parameter.", 400),
+    };
+
+    let kv = ctx.env.kv("GMAIL_AUTH")?;
+    let state_key = format!("oauth_state:{}", state);
+    let pending = match kv.get(&state_key).json::<PendingAuthorization>().await? {
+        Some(pending) => pending,
+        None => return Response::error("Unknown or expired `state`.", 400),
+    };
+    kv.delete(&state_key).await?;
+
+    let client_id = ctx.env.secret("GMAIL_CLIENT_ID")?.to_string();
+    let client_secret = ctx.env.secret("GMAIL_CLIENT_SECRET")?.to_string();
+
+    let tokens = match gmail::client::exchange_authorization_code(
+        &client_id,
+        &client_secret,
+        &code,
+        &pending.code_verifier,
+        &redirect_uri(&req)?,
+    )
+    .await
+    {
+        Ok(tokens) => tokens,
+        Err(e) => return Response::error(format!("Failed to exchange code: {}", e), 502),
+    };
+
+    let refresh_token = match tokens.refresh_token {
+        Some(token) => token,
+        None => {
+            return Response::error(
+                "Google did not return a refresh token. Revoke the app's access and retry.",
+                400,
+            )
+        }
+    };
+
+    let token_manager = TokenManager::new(
+        client_id,
+        client_secret,
+        refresh_token.clone(),
+        kv.clone(),
+        accounts::account_key(&pending.account, "access_token"),
+    );
+    token_manager.seed(&tokens.access_token, tokens.expires_in);
+    let profile = gmail::client::get_profile(&token_manager, "me").await?;
+    if !profile.email_address.eq_ignore_ascii_case(&pending.account) {
+        return Response::error(
+            format!(
+                "Authorized account {} does not match requested account {}; refresh token not stored.",
+                profile.email_address, pending.account
+            ),
+            403,
+        );
+    }
+
+    kv.put(
+        &accounts::account_key(&pending.account, "refresh_token"),
+        refresh_token,
+    )?
+    .execute()
+    .await?;
+    accounts::register_account(&ctx.env, &pending.account).await?;
+    // Replace any cached token issued for the previous refresh token, which
+    // may have had a narrower set of scopes.
+    token_manager
+        .store(&tokens.access_token, tokens.expires_in)
+        .await?;
+
+    let granted: Vec<&str> = tokens.scope.split_whitespace().collect();
+    let missing: Vec<&str> = REQUIRED_SCOPES
+        .iter()
+        .copied()
+        .filter(|scope| !granted.contains(scope))
+        .collect();
+
+    let mut lines = vec![
+        format!("Refresh token stored for {}.", profile.email_address),
+        "Granted scopes:".to_string(),
+    ];
+    lines.extend(granted.iter().map(|scope| format!("- {}", scope)));
+    if !missing.is_empty() {
+        lines.push("WARNING: the following required scopes were not granted:".to_string());
+        lines.extend(missing.iter().map(|scope| format!("- {}", scope)));
+    }
+
+    Response::ok(lines.join("\n"))
+}
+
+/// The callback URL on this worker's own origin. It must be registered as an
+/// authorized redirect URI on the Google OAuth client.
+fn redirect_uri(req: &Request) -> Result<String> {
+    let url = req.url()?;
+    Ok(format!(
+        "{}/oauth/callback",
+        url.origin().ascii_serialization()
+    ))
+}
+
+/// 32 random bytes, base64url-encoded: used for both `state` and the PKCE
+/// verifier (43 characters, within RFC 7636's 43..=128 limit).
+fn random_token() -> Result<String> {
+    let mut bytes = [0u8; 32];
+    getrandom::getrandom(&mut bytes)
+        .map_err(|e| Error::from(format!("Failed to generate random bytes: {}", e)))?;
+    Ok(URL_SAFE_NO_PAD.encode(bytes))
+}
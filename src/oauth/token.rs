@@ -0,0 +1,146 @@
+use crate::error::{Api, ApiError};
+use crate::gmail;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use worker::kv;
+
+type Result<T> = std::result::Result<T, ApiError>;
+
+/// Refresh this long before Google's stated expiry so a token never runs out
+/// between being handed out and being used.
+const EXPIRY_MARGIN_SECONDS: i64 = 5 * 60;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct CachedToken {
+    access_token: String,
+    /// Unix timestamp (seconds) after which the token must not be used.
+    expires_at: i64,
+}
+
+impl CachedToken {
+    fn is_fresh(&self) -> bool {
+        chrono::Utc::now().timestamp() < self.expires_at - EXPIRY_MARGIN_SECONDS
+    }
+}
+
+/// Hands out Google access tokens, caching them in memory and in KV until
+/// shortly before they expire, and retries requests once on a 401.
+pub struct TokenManager {
+    client_id: String,
+    client_secret: String,
+    refresh_token: String,
+    kv: kv::KvStore,
+    cache_key: String,
+    cached: RefCell<Option<CachedToken>>,
+}
+
+impl TokenManager {
+    pub fn new(
+        client_id: String,
+        client_secret: String,
+        refresh_token: String,
+        kv: kv::KvStore,
+        cache_key: String,
+    ) -> Self {
+        Self {
+            client_id,
+            client_secret,
+            refresh_token,
+            kv,
+            cache_key,
+            cached: RefCell::new(None),
+        }
+    }
+
+    /// Returns a valid access token, refreshing it only when neither the
+    /// in-memory nor the KV cache holds one that is still fresh.
+    pub async fn access_token(&self) -> Result<String> {
+        if let Some(token) = self.cached.borrow().as_ref().filter(|t| t.is_fresh()) {
+            return Ok(token.access_token.clone());
+        }
+
+        if let Some(token) = self
+            .kv
+            .get(&self.cache_key)
+            .json::<CachedToken>()
+            .await
+            .map_err(|e| ApiError::storage(Api::OAuth, e))?
+            .filter(|t| t.is_fresh())
+        {
+            let access_token = token.access_token.clone();
+            *self.cached.borrow_mut() = Some(token);
+            return Ok(access_token);
+        }
+
+        self.refresh().await
+    }
+
+    /// Exchanges the refresh token for a new access token and caches it.
+    pub async fn refresh(&self) -> Result<String> {
+        let response = gmail::client::get_access_token(
+            &self.client_id,
+            &self.client_secret,
+            &self.refresh_token,
+        )
+        .await?;
+        self.store(&response.access_token, response.expires_in)
+            .await?;
+        Ok(response.access_token)
+    }
+
+    /// Caches an access token obtained elsewhere (e.g. from the OAuth
+    /// callback) in memory only, without touching KV.
+    pub fn seed(&self, access_token: &str, expires_in: u32) {
+        *self.cached.borrow_mut() = Some(CachedToken {
+            access_token: access_token.to_string(),
+            expires_at: chrono::Utc::now().timestamp() + i64::from(expires_in),
+        });
+    }
+
+    /// Caches an access token in memory and in KV.
+    pub async fn store(&self, access_token: &str, expires_in: u32) -> Result<()> {
+        self.seed(access_token, expires_in);
+        let token = self.cached.borrow().clone();
+
+        // KV rejects TTLs below 60 seconds; such a token is never fresh anyway.
+        let ttl = u64::from(expires_in).max(60);
+        self.kv
+            .put(&self.cache_key, token)
+            .map_err(|e| ApiError::storage(Api::OAuth, e))?
+            .expiration_ttl(ttl)
+            .execute()
+            .await
+            .map_err(|e| ApiError::storage(Api::OAuth, e))?;
+
+        Ok(())
+    }
+
+    /// Sends `request` with a bearer token. If Google answers 401 the token is
+    /// refreshed and the request is sent exactly once more.
+    pub async fn send(
+        &self,
+        api: Api,
+        request: reqwest::RequestBuilder,
+    ) -> Result<reqwest::Response> {
+        let retry = request.try_clone();
+        let access_token = self.access_token().await?;
+
+        let res = request
+            .bearer_auth(access_token)
+            .send()
+            .await
+            .map_err(|e| ApiError::network(api, e))?;
+
+        match retry {
+            Some(retry) if res.status() == reqwest::StatusCode::UNAUTHORIZED => {
+                let access_token = self.refresh().await?;
+                retry
+                    .bearer_auth(access_token)
+                    .send()
+                    .await
+                    .map_err(|e| ApiError::network(api, e))
+            }
+            _ => Ok(res),
+        }
+    }
+}
@@ -0,0 +1,205 @@
+use crate::models::{GmailNotification, PubSubPushRequest};
+use crate::{accounts, authenticate, process_message};
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use worker::*;
+
+/// Per-account KV key holding the last Gmail `historyId` synced up to.
+const HISTORY_CHECKPOINT_KEY: &str = "gmail_history_id";
+/// Per-account KV key holding the watch expiration in epoch milliseconds.
+const WATCH_EXPIRATION_KEY: &str = "gmail_watch_expiration";
+/// Gmail watches expire after 7 days; renew once less than a day is left.
+const WATCH_RENEWAL_MARGIN_MS: i64 = 24 * 60 * 60 * 1000;
+
+/// Pub/Sub push endpoint. Decodes the Gmail notification, lists the messages
+/// added since the stored checkpoint and runs each one through the pipeline.
+pub async fn handle_notification(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    if !crate::is_authorized(&req, &ctx.env) {
+        return Response::error("Unauthorized", 401);
+    }
+
+    let push = match req.json::<PubSubPushRequest>().await {
+        Ok(push) => push,
+        Err(e) => return Response::error(format!("Invalid Pub/Sub payload: {}", e), 400),
+    };
+
+    let notification = match STANDARD
+        .decode(&push.message.data)
+        .ok()
+        .and_then(|data| serde_json::from_slice::<GmailNotification>(&data).ok())
+    {
+        Some(notification) => notification,
+        // Returning an error would only make Pub/Sub redeliver the same bad message.
+        None => return Response::ok("Ignored undecodable notification."),
+    };
+
+    let mut logs: Vec<String> = Vec::new();
+    logs.push(format!(
+        "Push notification {} for {} (historyId {}).",
+        push.message.message_id, notification.email_address, notification.history_id
+    ));
+
+    let user_email = match accounts::list_accounts(&ctx.env)
+        .await?
+        .into_iter()
+        .find(|account| account.eq_ignore_ascii_case(&notification.email_address))
+    {
+        Some(user_email) => user_email,
+        None => {
+            logs.push("- Notification is for an unregistered mailbox, ignoring.".to_string());
+            return Response::ok(logs.join("\n"));
+        }
+    };
+
+    let pipeline = authenticate(&ctx.env, &user_email, &mut logs).await?;
+    let checkpoint_key = accounts::account_key(&user_email, HISTORY_CHECKPOINT_KEY);
+
+    let kv = ctx.env.kv("GMAIL_AUTH")?;
+    let checkpoint = kv.get(&checkpoint_key).text().await?;
+
+    let messages = match checkpoint {
+        Some(start_history_id) => {
+            match pipeline.mail.list_history(&start_history_id).await {
+                Ok((messages, latest_history_id)) => {
+                    // Advance the checkpoint before processing so a Pub/Sub
+                    // redelivery does not draft the same messages twice.
+                    kv.put(&checkpoint_key, latest_history_id)?
+                        .execute()
+                        .await?;
+                    messages
+                }
+                Err(e) => {
+                    logs.push(format!(
+                        "- History sync from {} failed ({}), falling back to unread search.",
+                        start_history_id, e
+                    ));
+                    resync_from_unread(&kv, &checkpoint_key, &pipeline, notification.history_id)
+                        .await?
+                }
+            }
+        }
+        None => {
+            logs.push("- No history checkpoint stored, falling back to unread search.".to_string());
+            resync_from_unread(&kv, &checkpoint_key, &pipeline, notification.history_id).await?
+        }
+    };
+
+    logs.push(format!("Found {} new unread email(s).", messages.len()));
+    if messages.len() > pipeline.max_messages_per_run {
+        logs.push(format!(
+            "- Processing the first {}; the rest are left for the scheduled run.",
+            pipeline.max_messages_per_run
+        ));
+    }
+    let messages = &messages[..messages.len().min(pipeline.max_messages_per_run)];
+    for (i, message_id) in messages.iter().enumerate() {
+        if let Err(e) = process_message(&pipeline, i, message_id, &mut logs).await {
+            logs.push(format!(
+                "Stopping: {}. Remaining emails are left for the next scheduled run.",
+                e
+            ));
+            break;
+        }
+    }
+
+    Response::ok(logs.join("\n"))
+}
+
+/// Manually (re-)registers the Gmail watch of every account.
+pub async fn handle_watch_renewal(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    if !crate::is_authorized(&req, &ctx.env) {
+        return Response::error("Unauthorized", 401);
+    }
+
+    let mut logs: Vec<String> = Vec::new();
+    let mut failed = false;
+    for user_email in accounts::list_accounts(&ctx.env).await? {
+        if let Err(e) = renew_watch(&ctx.env, &user_email, &mut logs).await {
+            logs.push(format!(
+                "Failed to renew Gmail watch for {}: {}",
+                user_email, e
+            ));
+            failed = true;
+        }
+    }
+
+    if failed {
+        Response::error(logs.join("\n"), 500)
+    } else {
+        Response::ok(logs.join("\n"))
+    }
+}
+
+/// Renews each account's watch when it is missing or about to expire. Does
+/// nothing when no `GMAIL_PUBSUB_TOPIC` is configured.
+pub async fn renew_watches_if_expiring(env: &Env, logs: &mut Vec<String>) -> Result<()> {
+    if env.var("GMAIL_PUBSUB_TOPIC").is_err() {
+        return Ok(());
+    }
+
+    let kv = env.kv("GMAIL_AUTH")?;
+    let now_ms = chrono::Utc::now().timestamp_millis();
+    for user_email in accounts::list_accounts(env).await? {
+        let expiration = kv
+            .get(&accounts::account_key(&user_email, WATCH_EXPIRATION_KEY))
+            .text()
+            .await?
+            .and_then(|value| value.parse::<i64>().ok());
+
+        let expiring = match expiration {
+            Some(expires_at) => expires_at - now_ms <= WATCH_RENEWAL_MARGIN_MS,
+            None => true,
+        };
+        if expiring {
+            if let Err(e) = renew_watch(env, &user_email, logs).await {
+                logs.push(format!(
+                    "Failed to renew Gmail watch for {}: {}",
+                    user_email, e
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn renew_watch(env: &Env, user_email: &str, logs: &mut Vec<String>) -> Result<()> {
+    let topic_name = env.var("GMAIL_PUBSUB_TOPIC")?.to_string();
+    let pipeline = authenticate(env, user_email, logs).await?;
+
+    let watch = pipeline.mail.watch(&topic_name).await?;
+
+    let kv = env.kv("GMAIL_AUTH")?;
+    kv.put(
+        &accounts::account_key(user_email, WATCH_EXPIRATION_KEY),
+        &watch.expiration,
+    )?
+    .execute()
+    .await?;
+    let checkpoint_key = accounts::account_key(user_email, HISTORY_CHECKPOINT_KEY);
+    if kv.get(&checkpoint_key).text().await?.is_none() {
+        kv.put(&checkpoint_key, &watch.history_id)?
+            .execute()
+            .await?;
+    }
+
+    logs.push(format!(
+        "Gmail watch for {} registered on {} (expires at {} ms).",
+        user_email, topic_name, watch.expiration
+    ));
+    Ok(())
+}
+
+/// Used when there is no usable checkpoint: falls back to the unread search
+/// and restarts incremental sync from the notification's history ID.
+async fn resync_from_unread(
+    kv: &kv::KvStore,
+    checkpoint_key: &str,
+    pipeline: &crate::PipelineContext,
+    history_id: u64,
+) -> Result<Vec<crate::models::MessageId>> {
+    kv.put(checkpoint_key, history_id.to_string())?
+        .execute()
+        .await?;
+    let max_results = pipeline.max_messages_per_run.min(500) as u32;
+    let page = pipeline.mail.list_unread(None, max_results).await?;
+    Ok(page.messages.unwrap_or_default())
+}
@@ -0,0 +1,169 @@
+//! The `EMAIL_DRAFT_CONTEXT` Vectorize index of knowledge snippets ingested
+//! through `/knowledge`. `worker` 0.6 has no Vectorize API, so the binding's
+//! methods are declared here.
+//!
+//! Every account has its own namespace per embedding model (see `namespace`),
+//! so drafts for one mailbox never draw on another's knowledge, and vectors
+//! are only compared with vectors from the same model.
+
+use crate::models::ContextualDocument;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use wasm_bindgen::prelude::*;
+use worker::js_sys::{Float32Array, Object, Promise};
+use worker::wasm_bindgen_futures::JsFuture;
+use worker::{Env, EnvBinding, Result};
+
+/// Binding name declared in `wrangler.toml`.
+pub const CONTEXT_INDEX_BINDING: &str = "EMAIL_DRAFT_CONTEXT";
+
+#[wasm_bindgen]
+extern "C" {
+    #[wasm_bindgen(extends = Object)]
+    #[derive(Debug, Clone)]
+    pub type VectorizeIndex;
+
+    #[wasm_bindgen(method, catch)]
+    fn query(
+        this: &VectorizeIndex,
+        vector: &Float32Array,
+        options: JsValue,
+    ) -> std::result::Result<Promise, JsValue>;
+
+    #[wasm_bindgen(method, catch)]
+    fn upsert(this: &VectorizeIndex, vectors: JsValue) -> std::result::Result<Promise, JsValue>;
+
+    #[wasm_bindgen(method, catch, js_name = deleteByIds)]
+    fn delete_by_ids(this: &VectorizeIndex, ids: JsValue) -> std::result::Result<Promise, JsValue>;
+}
+
+impl EnvBinding for VectorizeIndex {
+    const TYPE_NAME: &'static str = "VectorizeIndex";
+
+    /// The runtime's class name for the binding is an implementation detail,
+    /// so it is not checked.
+    fn get(val: JsValue) -> Result<Self> {
+        Ok(val.unchecked_into())
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct QueryOptions<'a> {
+    top_k: u32,
+    return_metadata: &'static str,
+    namespace: &'a str,
+}
+
+#[derive(Deserialize)]
+struct QueryResult {
+    #[serde(default)]
+    matches: Vec<RawMatch>,
+}
+
+#[derive(Deserialize)]
+struct RawMatch {
+    id: String,
+    score: f64,
+    metadata: Option<serde_json::Value>,
+}
+
+/// A vector to store, with its document as metadata.
+#[derive(Serialize, Debug, Clone)]
+pub struct ContextVector {
+    pub id: String,
+    pub values: Vec<f32>,
+    /// See `namespace`.
+    pub namespace: String,
+    pub metadata: ContextualDocument,
+}
+
+/// The namespace of `account`'s vectors embedded with `embedding_model`: a
+/// hex SHA-256 of both, which fits Vectorize's 64-byte limit and keeps the
+/// address out of the index.
+pub fn namespace(account: &str, embedding_model: &str) -> String {
+    let digest = Sha256::digest(format!("{}\n{}", account.to_lowercase(), embedding_model));
+    digest.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// A stored document similar to the query vector.
+#[derive(Debug, Clone)]
+pub struct ContextMatch {
+    pub id: String,
+    /// Similarity to the query; higher is closer.
+    pub score: f64,
+    pub document: ContextualDocument,
+}
+
+/// Typed access to the context index.
+pub struct ContextIndex {
+    index: VectorizeIndex,
+}
+
+impl ContextIndex {
+    pub fn from_env(env: &Env) -> Result<Self> {
+        Ok(Self {
+            index: env.get_binding(CONTEXT_INDEX_BINDING)?,
+        })
+    }
+
+    /// The `top_k` documents in `namespace` closest to `vector`, best first.
+    /// Vectors whose metadata is not a `ContextualDocument` are skipped.
+    pub async fn query(
+        &self,
+        namespace: &str,
+        vector: &[f32],
+        top_k: u32,
+    ) -> Result<Vec<ContextMatch>> {
+        let options = serde_wasm_bindgen::to_value(&QueryOptions {
+            top_k,
+            return_metadata: "all",
+            namespace,
+        })?;
+        let promise = self.index.query(&Float32Array::from(vector), options)?;
+        let result: QueryResult = serde_wasm_bindgen::from_value(JsFuture::from(promise).await?)?;
+
+        Ok(result
+            .matches
+            .into_iter()
+            .filter_map(|m| {
+                let document = serde_json::from_value(m.metadata?).ok()?;
+                Some(ContextMatch {
+                    id: m.id,
+                    score: m.score,
+                    document,
+                })
+            })
+            .collect())
+    }
+
+    /// Inserts the vectors, replacing any stored under the same IDs.
+    pub async fn upsert(&self, vectors: &[ContextVector]) -> Result<()> {
+        let promise = self.index.upsert(serde_wasm_bindgen::to_value(vectors)?)?;
+        JsFuture::from(promise).await?;
+        Ok(())
+    }
+
+    pub async fn delete(&self, ids: &[String]) -> Result<()> {
+        let promise = self
+            .index
+            .delete_by_ids(serde_wasm_bindgen::to_value(ids)?)?;
+        JsFuture::from(promise).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn namespace_depends_on_account_and_model_only() {
+        let ns = namespace("Me@Example.com", "text-embedding-004");
+
+        assert_eq!(ns.len(), 64);
+        assert_eq!(ns, namespace("me@example.com", "text-embedding-004"));
+        assert_ne!(ns, namespace("you@example.com", "text-embedding-004"));
+        assert_ne!(ns, namespace("me@example.com", "nomic-embed-text"));
+    }
+}